@@ -1,29 +1,14 @@
-extern crate gcc;
 extern crate wayland_scanner;
 
 use wayland_scanner::{Side, generate_code, generate_interfaces};
-use gcc::Config;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 fn main() {
-    add_pam_build_flags();
     generate_wayland_protocols();
 }
 
-fn add_pam_build_flags() {
-    let mut config = Config::new();
-    config.flag("-Wall");
-    config.flag("-Wpedantic");
-    config.flag("-Werror");
-    config.file("src/pam/wrapper.c");
-    config.compile("libpamwrapper.a");
-
-    // Link against libpam
-    println!("cargo:rustc-flags=-l pam")
-}
-
 fn generate_wayland_protocols() {
     let protocols = fs::read_dir("./protocols")
         .expect("No <Way Cooler>/protocols/ directory");
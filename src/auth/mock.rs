@@ -0,0 +1,86 @@
+//! Authenticator that accepts a fixed set of credentials, so that the unlock
+//! flow can be tested without a PAM stack or a real account.
+
+use std::collections::HashMap;
+
+use pam::{Conversation, PamError};
+
+use super::Authenticator;
+
+/// Asks for each of a user's configured answers in turn, like a PAM stack
+/// with one module per answer would.
+pub struct MockAuthenticator {
+    users: HashMap<String, Vec<String>>,
+    /// Users whose account expired, who are turned away after answering.
+    expired: Vec<String>,
+    /// What every attempt fails with, like a broken PAM stack would.
+    error: Option<PamError>
+}
+
+impl MockAuthenticator {
+    pub fn new() -> Self {
+        MockAuthenticator {
+            users: HashMap::new(),
+            expired: Vec::new(),
+            error: None
+        }
+    }
+
+    /// Adds a user that is let in after giving all of the answers, in order.
+    pub fn with_user(mut self, username: &str, answers: &[&str]) -> Self {
+        let answers = answers.iter().map(|answer| answer.to_string()).collect();
+        self.users.insert(username.into(), answers);
+        self
+    }
+
+    /// Turns the user away once they're authenticated, like PAM does when
+    /// their account expired.
+    pub fn with_expired_account(mut self, username: &str) -> Self {
+        self.expired.push(username.into());
+        self
+    }
+
+    /// Fails every attempt with the error, before asking anything.
+    pub fn with_error(mut self, error: PamError) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+impl Authenticator for MockAuthenticator {
+    fn authenticate(&self,
+                    username: &str,
+                    mut conversation: Box<dyn Conversation>) -> Result<(), PamError> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone())
+        }
+        let answers = self.users.get(username)
+            .ok_or(PamError::UserUnknown)?;
+        for (index, expected) in answers.iter().enumerate() {
+            let text = if index == 0 {
+                "Password: ".into()
+            } else {
+                format!("Answer {}: ", index + 1)
+            };
+            let answer = conversation.prompt(&text, false)
+                .map_err(|_| PamError::ConversationError)?;
+            if answer.as_str() != expected {
+                conversation.error("Wrong answer");
+                return Err(PamError::AuthError)
+            }
+        }
+        self.admit(username, conversation)
+    }
+
+    fn admit(&self,
+             username: &str,
+             _: Box<dyn Conversation>) -> Result<(), PamError> {
+        if !self.users.contains_key(username) {
+            Err(PamError::UserUnknown)
+        } else if self.expired.iter().any(|expired| expired == username) {
+            Err(PamError::AccountExpired)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Deciding whether the person at the keyboard is allowed to unlock.
+//!
+//! `Input` only talks to an `Authenticator`, through a `Transaction`.
+//! PAM is the one used for real, tests use `mock::MockAuthenticator`.
+
+mod transaction;
+#[cfg(test)]
+pub mod mock;
+
+pub use self::transaction::{Event, Message, Transaction};
+
+use std::ffi::CStr;
+
+use libc::{getuid, getpwuid};
+
+use pam::{Conversation, PamError};
+
+/// Something that can check a user's credentials.
+///
+/// Authentication is a conversation: the authenticator asks as many questions
+/// as it needs through the `Conversation`, which may block until the user
+/// answers. Because of that it is always run on its own thread.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self,
+                    username: &str,
+                    conversation: Box<dyn Conversation>) -> Result<(), PamError>;
+    /// Lets in a user who already proved who they are some other way, like
+    /// with their fingerprint, if their account still allows it.
+    fn admit(&self,
+             username: &str,
+             conversation: Box<dyn Conversation>) -> Result<(), PamError>;
+}
+
+/// Gets the name of the user running this program.
+pub fn current_username() -> String {
+    unsafe {
+        let uid = getuid();
+        let pwuid = getpwuid(uid);
+        let slice = CStr::from_ptr((*pwuid).pw_name);
+        slice.to_string_lossy().into_owned()
+    }
+}
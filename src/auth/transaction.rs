@@ -0,0 +1,140 @@
+//! Runs authentication on its own thread, passing the conversation back to
+//! the main loop over channels.
+
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use pam::{Conversation, PamError};
+use secret::SecretBuffer;
+use wakeup::Waker;
+
+use super::Authenticator;
+
+/// A message PAM sent as part of the conversation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Message {
+    /// PAM wants an answer. If `echo` is false it is secret (e.g a password)
+    /// and must not be shown on screen.
+    Prompt { text: String, echo: bool },
+    /// Informational message, no answer expected.
+    Info(String),
+    /// Error message, no answer expected.
+    Error(String)
+}
+
+/// What happened next in an ongoing `Transaction`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// PAM sent a message. If it was a `Message::Prompt` it is waiting
+    /// for `Transaction::answer` to be called.
+    Message(Message),
+    /// Authentication finished.
+    Done(Result<(), PamError>)
+}
+
+/// A single authentication attempt running on its own thread.
+///
+/// Dropping the transaction before it is `Event::Done` aborts it, the
+/// pending prompt is answered with a conversation error.
+pub struct Transaction {
+    events: Receiver<Event>,
+    answers: Sender<SecretBuffer>
+}
+
+impl Transaction {
+    /// Starts authenticating the user.
+    ///
+    /// The waker is used every time a new event is ready.
+    pub fn start(authenticator: Arc<dyn Authenticator>,
+                 username: String,
+                 waker: Waker) -> Self {
+        Transaction::spawn(waker, move |conversation| {
+            authenticator.authenticate(&username, conversation)
+        })
+    }
+
+    /// Starts letting in a user who already proved who they are, which
+    /// still checks their account and refreshes their credentials.
+    pub fn admit(authenticator: Arc<dyn Authenticator>,
+                 username: String,
+                 waker: Waker) -> Self {
+        Transaction::spawn(waker, move |conversation| {
+            authenticator.admit(&username, conversation)
+        })
+    }
+
+    /// Runs `step` on its own thread, passing the conversation on.
+    fn spawn<F>(waker: Waker, step: F) -> Self
+        where F: FnOnce(Box<dyn Conversation>) -> Result<(), PamError> + Send + 'static
+    {
+        let (event_tx, events) = channel();
+        let (answers, answer_rx) = channel();
+        thread::spawn(move || {
+            let conversation = ChannelConversation {
+                events: event_tx.clone(),
+                answers: answer_rx,
+                waker: waker.clone()
+            };
+            let result = step(Box::new(conversation));
+            // The other end might have given up on us already.
+            let _ = event_tx.send(Event::Done(result));
+            waker.wake();
+        });
+        Transaction {
+            events,
+            answers
+        }
+    }
+
+    /// Gets the next event, if PAM has sent one.
+    ///
+    /// This never blocks.
+    pub fn next_event(&self) -> Option<Event> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Event::Done(Err(PamError::ConversationError)))
+            }
+        }
+    }
+
+    /// Answers the last prompt PAM sent.
+    ///
+    /// The buffer is moved to the other thread, its contents aren't copied.
+    pub fn answer(&self, answer: SecretBuffer) {
+        // If the thread is gone then the next event will say so.
+        let _ = self.answers.send(answer);
+    }
+}
+
+/// Passes the conversation on to the `Transaction`.
+struct ChannelConversation {
+    events: Sender<Event>,
+    answers: Receiver<SecretBuffer>,
+    waker: Waker
+}
+
+impl ChannelConversation {
+    fn send(&mut self, message: Message) -> Result<(), ()> {
+        self.events.send(Event::Message(message)).map_err(|_| ())?;
+        self.waker.wake();
+        Ok(())
+    }
+}
+
+impl Conversation for ChannelConversation {
+    fn prompt(&mut self, text: &str, echo: bool) -> Result<SecretBuffer, ()> {
+        self.send(Message::Prompt { text: text.into(), echo })?;
+        self.answers.recv().map_err(|_| ())
+    }
+
+    fn info(&mut self, text: &str) {
+        let _ = self.send(Message::Info(text.into()));
+    }
+
+    fn error(&mut self, text: &str) {
+        let _ = self.send(Message::Error(text.into()));
+    }
+}
@@ -0,0 +1,177 @@
+//! Dead keys and Compose sequences, through libxkbcommon.
+//!
+//! `wayland_kbd` only gives the text each key produces on its own, so a dead
+//! key followed by a letter would come out as two characters. The key presses
+//! are run through the compose table of the user's locale first, the same one
+//! their terminal uses, so the password comes out the same.
+
+use std::env;
+use std::ffi::CString;
+
+use libc::c_char;
+
+use secret;
+use xkb::*;
+
+/// Longest text a compose sequence can produce, including the terminator.
+const MAX_TEXT: usize = 256;
+
+/// What a key press amounts to, once it went through the compose table.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Composed {
+    /// Not part of a sequence, the key's own text should be used.
+    Nothing,
+    /// In the middle of a sequence, the key produces no text yet.
+    Composing,
+    /// The key finished a sequence, this text should be used instead of its
+    /// own.
+    Text(String),
+    /// The key didn't fit the sequence that was started, so the whole
+    /// sequence and the key are dropped.
+    Cancelled
+}
+
+pub struct Compose {
+    state: *mut xkb_compose_state
+}
+
+// The state is only ever used through `&mut self`.
+unsafe impl Send for Compose {}
+
+impl Compose {
+    /// Loads the compose table for the user's locale, as well as their
+    /// `~/.XCompose` if they have one.
+    pub fn from_locale() -> Option<Self> {
+        let locale = CString::new(locale()).ok()?;
+        Compose::new(|context| unsafe {
+            xkb_compose_table_new_from_locale(context, locale.as_ptr(),
+                                              XKB_COMPOSE_COMPILE_NO_FLAGS)
+        })
+    }
+
+    /// Loads a compose table in the format of `Compose(5)`.
+    pub fn from_table(table: &str) -> Option<Self> {
+        let locale = CString::new("C").unwrap();
+        Compose::new(|context| unsafe {
+            xkb_compose_table_new_from_buffer(context,
+                                              table.as_ptr() as *const c_char,
+                                              table.len(),
+                                              locale.as_ptr(),
+                                              XKB_COMPOSE_FORMAT_TEXT_V1,
+                                              XKB_COMPOSE_COMPILE_NO_FLAGS)
+        })
+    }
+
+    fn new<F>(new_table: F) -> Option<Self>
+        where F: FnOnce(*mut xkb_context) -> *mut xkb_compose_table
+    {
+        unsafe {
+            let context = xkb_context_new(XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None
+            }
+            let table = new_table(context);
+            // The table keeps its own reference to the context.
+            xkb_context_unref(context);
+            if table.is_null() {
+                return None
+            }
+            let state = xkb_compose_state_new(table, XKB_COMPOSE_STATE_NO_FLAGS);
+            xkb_compose_table_unref(table);
+            if state.is_null() {
+                return None
+            }
+            Some(Compose { state })
+        }
+    }
+
+    /// Feeds a key press to the compose table.
+    pub fn feed(&mut self, keysym: u32) -> Composed {
+        unsafe {
+            if xkb_compose_state_feed(self.state, keysym) == XKB_COMPOSE_FEED_IGNORED {
+                // Modifiers don't affect sequences.
+                return Composed::Nothing
+            }
+            match xkb_compose_state_get_status(self.state) {
+                XKB_COMPOSE_COMPOSING => Composed::Composing,
+                XKB_COMPOSE_CANCELLED => {
+                    xkb_compose_state_reset(self.state);
+                    Composed::Cancelled
+                },
+                XKB_COMPOSE_COMPOSED => {
+                    let mut buffer = [0u8; MAX_TEXT];
+                    let len = xkb_compose_state_get_utf8(self.state,
+                                                         buffer.as_mut_ptr() as *mut c_char,
+                                                         buffer.len());
+                    xkb_compose_state_reset(self.state);
+                    if len <= 0 {
+                        // Sequences can end in a keysym without any text,
+                        // which isn't worth typing.
+                        return Composed::Nothing
+                    }
+                    let len = ::std::cmp::min(len as usize, MAX_TEXT - 1);
+                    let text = String::from_utf8_lossy(&buffer[..len]).into_owned();
+                    secret::zero(&mut buffer);
+                    Composed::Text(text)
+                },
+                _ => Composed::Nothing
+            }
+        }
+    }
+
+    /// Forgets about the sequence that was started, if there is one.
+    pub fn reset(&mut self) {
+        unsafe { xkb_compose_state_reset(self.state) }
+    }
+}
+
+impl Drop for Compose {
+    fn drop(&mut self) {
+        unsafe {
+            xkb_compose_state_unref(self.state);
+        }
+    }
+}
+
+/// The locale that decides which compose table is used, looked up the same
+/// way as `setlocale` does for `LC_CTYPE`.
+fn locale() -> String {
+    ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_else(|| "C".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use wayland_kbd::keysyms::{XKB_KEY_Multi_key, XKB_KEY_Shift_L, XKB_KEY_dead_acute,
+                               XKB_KEY_c, XKB_KEY_e, XKB_KEY_l, XKB_KEY_o, XKB_KEY_slash,
+                               XKB_KEY_x};
+
+    use super::{Compose, Composed};
+
+    const TABLE: &'static str = "\
+<dead_acute> <e> : \"\u{e9}\" eacute\n\
+<Multi_key> <o> <slash> : \"\u{f8}\" oslash\n\
+<Multi_key> <c> <l> : Caps_Lock\n";
+
+    #[test]
+    fn sequences() {
+        let mut compose = Compose::from_table(TABLE)
+            .expect("Could not load compose table");
+        assert_eq!(compose.feed(XKB_KEY_e), Composed::Nothing);
+        assert_eq!(compose.feed(XKB_KEY_dead_acute), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_e), Composed::Text("\u{e9}".into()));
+        assert_eq!(compose.feed(XKB_KEY_Multi_key), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_Shift_L), Composed::Nothing);
+        assert_eq!(compose.feed(XKB_KEY_o), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_slash), Composed::Text("\u{f8}".into()));
+        assert_eq!(compose.feed(XKB_KEY_dead_acute), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_x), Composed::Cancelled);
+        assert_eq!(compose.feed(XKB_KEY_x), Composed::Nothing);
+        // Caps_Lock has no text.
+        assert_eq!(compose.feed(XKB_KEY_Multi_key), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_c), Composed::Composing);
+        assert_eq!(compose.feed(XKB_KEY_l), Composed::Nothing);
+    }
+}
@@ -0,0 +1,272 @@
+//! On-screen controls, for when clicking or tapping is easier than reaching
+//! for the keyboard.
+//!
+//! The controls are rows of buttons along the bottom of every lock surface,
+//! with an on-screen keypad above the other buttons if one is enabled.
+//! `Input` decides which ones are shown, and where they end up only depends
+//! on the size of the surface, so clicks can be matched to them without
+//! keeping track of what was drawn. Like clicks, they are in logical units.
+
+use wayland_client::protocol::wl_surface;
+
+use ::color::Color;
+use ::font::{self, GLYPH_SIZE, SCALE};
+use ::window::Resolution;
+
+/// Space between the label of a button and its border, in logical units.
+const PADDING: u32 = GLYPH_SIZE * SCALE / 2;
+/// Height of a button.
+const ROW_HEIGHT: u32 = GLYPH_SIZE * SCALE + 2 * PADDING;
+/// Space between two buttons, and between the buttons and the bottom of the
+/// surface.
+const GAP: u32 = GLYPH_SIZE * SCALE;
+/// How thick the border of a button is.
+const BORDER: u32 = 2;
+
+/// What a control does when it's clicked.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Same as pressing Return.
+    Submit,
+    /// Same as pressing Escape.
+    Clear,
+    /// Switches to the next keyboard layout.
+    SwitchLayout,
+    /// Types a character, for the keys of the keypad.
+    Type(char),
+    /// Same as pressing BackSpace.
+    Delete,
+    /// Switches the letters of the keypad between lower and upper case.
+    Shift
+}
+
+/// The on-screen keypads there are, for when there's no keyboard to type on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Keypad {
+    /// Only digits, for numeric PINs.
+    Pin,
+    /// Digits and the letters of a QWERTY keyboard.
+    Qwerty
+}
+
+impl Keypad {
+    /// The rows of keys, from top to bottom, with capital letters if
+    /// `shifted`.
+    pub fn rows(self, shifted: bool) -> Vec<Vec<Control>> {
+        let keys = |chars: &str| -> Vec<Control> {
+            chars.chars()
+                .map(|chr| if shifted { chr.to_ascii_uppercase() } else { chr })
+                .map(|chr| Control::new(&chr.to_string(), Action::Type(chr)))
+                .collect()
+        };
+        match self {
+            Keypad::Pin => {
+                let mut last = vec![Control::new("Del", Action::Delete)];
+                last.extend(keys("0"));
+                vec![keys("123"), keys("456"), keys("789"), last]
+            },
+            Keypad::Qwerty => {
+                let mut last = vec![Control::new("Shift", Action::Shift)];
+                last.extend(keys("zxcvbnm"));
+                last.push(Control::new("Del", Action::Delete));
+                vec![keys("1234567890"), keys("qwertyuiop"), keys("asdfghjkl"), last,
+                     vec![Control::new("Space", Action::Type(' '))]]
+            }
+        }
+    }
+}
+
+/// A control, as `Input` describes it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Control {
+    pub label: String,
+    pub action: Action
+}
+
+impl Control {
+    pub fn new(label: &str, action: Action) -> Self {
+        Control {
+            label: label.into(),
+            action
+        }
+    }
+}
+
+/// A click or tap on a lock surface, in surface coordinates.
+pub struct Click {
+    pub surface: wl_surface::WlSurface,
+    pub x: f64,
+    pub y: f64,
+    /// Where the finger touched the surface, for a tap. It only counts if
+    /// that's on the same control as where it was lifted.
+    pub down: (f64, f64)
+}
+
+/// Where a control is on a surface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Area {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32
+}
+
+impl Area {
+    fn contains(self, x: f64, y: f64) -> bool {
+        x >= self.x as f64 && x < (self.x + self.w) as f64
+            && y >= self.y as f64 && y < (self.y + self.h) as f64
+    }
+}
+
+/// How much room the rows of controls take up at the bottom of a surface.
+pub fn height(rows: &[Vec<Control>]) -> u32 {
+    rows.len() as u32 * (ROW_HEIGHT + GAP)
+}
+
+/// Lays the rows of controls out centered at the bottom of the surface, one
+/// below the other.
+fn place(res: Resolution, rows: &[Vec<Control>]) -> Vec<Area> {
+    let mut areas = Vec::new();
+    let mut y = res.height().saturating_sub(height(rows));
+    for row in rows {
+        let widths: Vec<u32> = row.iter()
+            .map(|control| font::text_width(&control.label) + 2 * PADDING)
+            .collect();
+        let total = widths.iter().sum::<u32>()
+            + GAP * (widths.len() as u32).saturating_sub(1);
+        let mut x = res.width().saturating_sub(total) / 2;
+        for w in widths {
+            areas.push(Area { x, y, w, h: ROW_HEIGHT });
+            x += w + GAP;
+        }
+        y += ROW_HEIGHT + GAP;
+    }
+    areas
+}
+
+/// Which control is at `(x, y)`, counting through the rows from the top.
+fn hit(res: Resolution, rows: &[Vec<Control>], (x, y): (f64, f64)) -> Option<usize> {
+    place(res, rows).into_iter().position(|area| area.contains(x, y))
+}
+
+/// What a click or tap does, that went down at `down` and was let go at
+/// `up`. Nothing, unless both are on the same control: sliding off of a
+/// control or onto one doesn't press it.
+pub fn tap(res: Resolution,
+           rows: &[Vec<Control>],
+           down: (f64, f64),
+           up: (f64, f64)) -> Option<Action> {
+    let index = hit(res, rows, up)?;
+    if hit(res, rows, down) != Some(index) {
+        return None
+    }
+    rows.iter().flatten().nth(index).map(|control| control.action)
+}
+
+/// Draws the controls onto the raw buffer of a surface, four bytes per pixel.
+pub fn draw(pixels: &mut [u8], res: Resolution, rows: &[Vec<Control>], color: Color) {
+    let controls = rows.iter().flatten();
+    for (area, control) in place(res, rows).into_iter().zip(controls) {
+        draw_border(pixels, res, area, color);
+        font::draw_text(pixels, res, area.x + PADDING, area.y + PADDING,
+                        &control.label, color);
+    }
+}
+
+fn draw_border(pixels: &mut [u8], res: Resolution, area: Area, color: Color) {
+    let Area { x, y, w, h } = area;
+    res.fill(pixels, x, y, w, BORDER, color);
+    res.fill(pixels, x, (y + h).saturating_sub(BORDER), w, BORDER, color);
+    res.fill(pixels, x, y, BORDER, h, color);
+    res.fill(pixels, (x + w).saturating_sub(BORDER), y, BORDER, h, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use window::{Resolution, Transform};
+
+    use super::{draw, place, tap, Action, Control, Keypad, BORDER};
+
+    #[test]
+    fn clicks_hit_the_control_under_them() {
+        let res = Resolution { w: 800, h: 600, scale: 1, transform: Transform::Normal };
+        let controls = vec![vec![Control::new("Unlock", Action::Submit),
+                                 Control::new("Clear", Action::Clear)]];
+        let areas = place(res, &controls);
+        assert!(areas[0].x + areas[0].w < areas[1].x, "Controls overlap");
+        assert!(areas[1].y + areas[1].h < res.h, "Controls are off screen");
+        let center = |index: usize| {
+            let area = areas[index];
+            ((area.x + area.w / 2) as f64, (area.y + area.h / 2) as f64)
+        };
+        let (x, y) = center(0);
+        assert_eq!(tap(res, &controls, (x, y), (x, y)), Some(Action::Submit));
+        let (x, y) = center(1);
+        assert_eq!(tap(res, &controls, (x, y), (x, y)), Some(Action::Clear));
+        assert_eq!(tap(res, &controls, (x, 0.0), (x, 0.0)), None);
+        let between = ((areas[0].x + areas[0].w + 1) as f64, y);
+        assert_eq!(tap(res, &controls, between, between), None);
+    }
+
+    #[test]
+    fn sliding_between_controls_cancels_the_tap() {
+        let res = Resolution { w: 800, h: 600, scale: 1, transform: Transform::Normal };
+        let controls = vec![vec![Control::new("Unlock", Action::Submit),
+                                 Control::new("Clear", Action::Clear)]];
+        let areas = place(res, &controls);
+        let center = |index: usize| {
+            let area = areas[index];
+            ((area.x + area.w / 2) as f64, (area.y + area.h / 2) as f64)
+        };
+        let inside = (areas[0].x as f64 + 1.0, areas[0].y as f64 + 1.0);
+        let outside = (center(0).0, 0.0);
+        assert_eq!(tap(res, &controls, center(0), inside), Some(Action::Submit));
+        assert_eq!(tap(res, &controls, center(0), center(1)), None);
+        assert_eq!(tap(res, &controls, center(0), outside), None);
+        assert_eq!(tap(res, &controls, outside, center(0)), None);
+    }
+
+    #[test]
+    fn keypads_fit_on_screen() {
+        let res = Resolution { w: 1024, h: 768, scale: 1, transform: Transform::Normal };
+        for &keypad in &[Keypad::Pin, Keypad::Qwerty] {
+            let rows = keypad.rows(false);
+            for area in place(res, &rows) {
+                assert!(area.x + area.w <= res.w && area.y + area.h <= res.h,
+                        "{:?} does not fit", keypad);
+            }
+        }
+        let rows = Keypad::Qwerty.rows(true);
+        assert!(rows[1].contains(&Control::new("Q", Action::Type('Q'))));
+    }
+
+    #[test]
+    fn controls_are_laid_out_in_logical_units() {
+        let controls = vec![vec![Control::new("Unlock", Action::Submit)]];
+        let res = Resolution { w: 800, h: 600, scale: 1, transform: Transform::Normal };
+        let hidpi = Resolution { w: 1600, h: 1200, scale: 2, transform: Transform::Normal };
+        assert_eq!(place(res, &controls), place(hidpi, &controls));
+        let area = place(hidpi, &controls)[0];
+        let (x, y) = ((area.x + 1) as f64, (area.y + 1) as f64);
+        assert_eq!(tap(hidpi, &controls, (x, y), (x, y)), Some(Action::Submit));
+    }
+
+    #[test]
+    fn controls_are_drawn_at_the_output_scale() {
+        let controls = vec![vec![Control::new("Unlock", Action::Submit)]];
+        let hidpi = Resolution { w: 1600, h: 1200, scale: 2, transform: Transform::Normal };
+        let mut pixels = vec![0; (hidpi.size() * 4) as usize];
+        draw(&mut pixels, hidpi, &controls, 0xFFFFFF.into());
+        let area = place(hidpi, &controls)[0];
+        let is_drawn = |x: u32, y: u32| {
+            let offset = ((y * hidpi.w + x) * 4) as usize;
+            pixels[offset..offset + 4] != [0; 4]
+        };
+        // Every logical unit of the border is two pixels wide.
+        let (x, y) = (area.x * 2, area.y * 2 + 2 * BORDER);
+        for dx in 0..2 * BORDER {
+            assert!(is_drawn(x + dx, y), "Border has a gap");
+        }
+        assert!(!is_drawn(x + 2 * BORDER, y), "Border is too thick");
+    }
+}
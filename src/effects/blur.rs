@@ -6,7 +6,7 @@ use dbus::arg::Array;
 use wayland_client;
 use ::window::{Window, Resolution};
 use image::png::PNGEncoder;
-use image::{ColorType, DynamicImage, SubImage, load_from_memory, imageops};
+use image::{ColorType, DynamicImage, GenericImage, SubImage, load_from_memory, imageops};
 
 /// How long to wait until d-bus timeout
 const DBUS_WAIT_TIME: i32 = 10000;
@@ -27,6 +27,8 @@ impl Blur {
                -> Self {
         let res: Resolution = *state.get_handler(resolution_id);
         let window: &mut Window = state.get_mut_handler(window_id);
+        // The screen is in the output's own orientation, which is how the
+        // buffer is too, so it doesn't need to be rotated.
         let image = get_screen(res, output);
         window.write_bytes(res, &image.to_rgba().into_raw());
         Blur {
@@ -42,8 +44,10 @@ impl Blur {
                 mut state: wayland_client::StateGuard) {
         // TODO FIXME This is a bottle neck :(
         // See this issue https://github.com/PistonDevelopers/image/issues/615
-        self.image = self.image.blur(amount);
         let res: Resolution = *state.get_handler(resolution_id);
+        self.fit(res);
+        // The amount is in logical units, like everything else on screen.
+        self.image = self.image.blur(amount * res.buffer_scale() as f32);
         let window: &mut Window = state.get_mut_handler(self.window_id);
         window.write_bytes(res, &self.image.to_rgba().into_raw());
     }
@@ -51,16 +55,28 @@ impl Blur {
     /// Puts random circles to signify input.
     pub fn random_input_circles(&mut self, res: Resolution,
                                 state: &mut wayland_client::StateGuard) {
-        let x = ::rand::random::<u32>() % (res.w - 32);
-        let y = ::rand::random::<u32>() % (res.h - 32);
-        let w = 32;
-        let h = 32;
+        self.fit(res);
+        let w = 32 * res.buffer_scale();
+        let h = 32 * res.buffer_scale();
+        let x = ::rand::random::<u32>() % (res.w - w);
+        let y = ::rand::random::<u32>() % (res.h - h);
         let mut sub_image = DynamicImage::ImageRgba8(SubImage::new(&mut self.image, x, y, w, h).to_image());
         sub_image.invert();
         imageops::replace(&mut self.image, &sub_image, x, y);
         let window: &mut Window = state.get_mut_handler(self.window_id);
         window.write_bytes(res, &self.image.to_rgba().into_raw());
     }
+
+    /// Stretches the image to the output's size, if it changed since the
+    /// screen was captured.
+    ///
+    /// The screen can't be captured again, it's already covered by the lock
+    /// screen.
+    fn fit(&mut self, res: Resolution) {
+        if self.image.dimensions() != (res.w, res.h) {
+            self.image = self.image.resize_exact(res.w, res.h, imageops::FilterType::Triangle);
+        }
+    }
 }
 
 fn get_screen(res: Resolution, output: u32) -> DynamicImage {
@@ -78,6 +94,10 @@ fn get_screen(res: Resolution, output: u32) -> DynamicImage {
         .expect("Way Cooler returned an unexpected value")
         .collect::<Vec<u8>>();
     convert_to_png(&mut pixels);
+    // way-cooler's `Scrape` reads the output back with `wlc_pixels_read`
+    // over the whole of `get_resolution()`, which is `glReadPixels` on the
+    // output's framebuffer: the size of its mode, in its own orientation and
+    // bottom row first. So it's the size of the buffer, and only upside down.
     let mut png_buf = Vec::with_capacity(4 * (res.w * res.h) as usize);
     {
         let encoder = PNGEncoder::new(&mut png_buf);
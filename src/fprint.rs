@@ -0,0 +1,367 @@
+//! Fingerprint authentication through fprintd, over D-Bus.
+//!
+//! It runs on its own thread alongside password entry, so whichever of the
+//! two succeeds first unlocks the screen. Stopping it never waits for the
+//! thread, since fprintd can take a while to answer: the next verification
+//! waits for the reader to be released instead.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use dbus::{BusType, Connection, ConnectionItem, Message, MessageItem};
+
+use wakeup::Waker;
+
+const MANAGER_PATH: &'static str = "/net/reactivated/Fprint/Manager";
+const MANAGER_INTERFACE: &'static str = "net.reactivated.Fprint.Manager";
+const DEVICE_INTERFACE: &'static str = "net.reactivated.Fprint.Device";
+/// How long to wait for fprintd to answer a method call, in milliseconds.
+const DBUS_WAIT_TIME: i32 = 5000;
+/// How often the worker checks if it should stop, in milliseconds.
+const STOP_CHECK_INTERVAL: i32 = 100;
+
+/// Where to find fprintd.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bus: BusType,
+    /// Bus name fprintd is reachable at.
+    pub service: String,
+    /// Held by whichever verification has the reader claimed, shared by
+    /// every verification started with this config.
+    pub reader: Arc<Mutex<()>>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bus: BusType::System,
+            service: "net.reactivated.Fprint".into(),
+            reader: Arc::new(Mutex::new(()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Something to show the user, like asking them to scan again.
+    Status(String),
+    /// The finger didn't match, which counts as a failed attempt. It comes
+    /// after the `Status` saying so.
+    NoMatch,
+    /// The finger matched, the user can be let in.
+    Matched,
+    /// Fingerprint authentication can't be used, e.g because there is no
+    /// reader. It won't send anything else after this.
+    Unavailable(String)
+}
+
+/// A fingerprint verification running in the background.
+///
+/// The device is released shortly after this is dropped.
+pub struct Fingerprint {
+    events: Receiver<Event>,
+    stop: Arc<AtomicBool>
+}
+
+impl Fingerprint {
+    /// Claims the reader for the user and starts verifying.
+    ///
+    /// The main loop is woken up whenever there's a new event.
+    pub fn start(config: Config, username: String, waker: Waker) -> Self {
+        let (sender, events) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        thread::spawn(move || {
+            let notify = |event| {
+                let _ = sender.send(event);
+                waker.wake();
+            };
+            // Wait for the last verification to let go of the reader.
+            let _reader = config.reader.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if worker_stop.load(Ordering::SeqCst) {
+                return
+            }
+            if let Err(err) = verify(&config, &username, &worker_stop, &notify) {
+                notify(Event::Unavailable(err));
+            }
+        });
+        Fingerprint {
+            events,
+            stop
+        }
+    }
+
+    /// Gets the next event without blocking, if there is one.
+    pub fn next_event(&self) -> Option<Event> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) |
+            Err(TryRecvError::Disconnected) => None
+        }
+    }
+}
+
+impl Drop for Fingerprint {
+    fn drop(&mut self) {
+        // The worker stops on its own, without blocking the main loop.
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The reader fprintd picked, which all calls after claiming go to.
+struct Device<'a> {
+    con: &'a Connection,
+    service: &'a str,
+    path: String
+}
+
+impl<'a> Device<'a> {
+    fn call(&self, method: &str, args: Vec<MessageItem>) -> Result<Message, String> {
+        call(self.con, self.service, &self.path, DEVICE_INTERFACE, method, args)
+    }
+
+    /// Stops verifying and lets go of the reader. Errors are ignored, since
+    /// fprintd cleans up after clients that disappear anyways.
+    fn release(&self) {
+        let _ = self.call("VerifyStop", vec![]);
+        let _ = self.call("Release", vec![]);
+    }
+}
+
+/// Verifies the user until their finger matches, something goes wrong or
+/// `stop` is set.
+fn verify<F>(config: &Config,
+             username: &str,
+             stop: &AtomicBool,
+             notify: &F) -> Result<(), String>
+    where F: Fn(Event)
+{
+    let con = Connection::get_private(config.bus)
+        .map_err(|err| format!("Could not get d-bus connection: {}", err))?;
+    let reply = call(&con, &config.service, MANAGER_PATH, MANAGER_INTERFACE,
+                     "GetDefaultDevice", vec![])?;
+    let path = match reply.get_items().into_iter().next() {
+        Some(MessageItem::ObjectPath(path)) => (*path).to_owned(),
+        _ => return Err("fprintd returned an unexpected value".into())
+    };
+    let device = Device { con: &con, service: &config.service, path };
+    con.add_match(&format!("type='signal',sender='{}',path='{}',\
+                            interface='{}',member='VerifyStatus'",
+                           config.service, device.path, DEVICE_INTERFACE))
+        .map_err(|err| format!("Could not listen to fprintd: {}", err))?;
+    device.call("Claim", vec![MessageItem::Str(username.into())])?;
+    let result = run(&con, &device, stop, notify);
+    device.release();
+    result
+}
+
+/// Keeps verifying until the finger matches or `stop` is set.
+fn run<F>(con: &Connection,
+          device: &Device,
+          stop: &AtomicBool,
+          notify: &F) -> Result<(), String>
+    where F: Fn(Event)
+{
+    device.call("VerifyStart", vec![MessageItem::Str("any".into())])?;
+    notify(Event::Status("Scan your finger or type your password".into()));
+    let mut items = con.iter(STOP_CHECK_INTERVAL);
+    while !stop.load(Ordering::SeqCst) {
+        let signal = match items.next() {
+            Some(ConnectionItem::Signal(signal)) => signal,
+            _ => continue
+        };
+        let (_, path, _, member) = signal.headers();
+        if path.as_ref() != Some(&device.path) ||
+            member.as_ref().map(|member| member.as_str()) != Some("VerifyStatus") {
+                continue
+            }
+        let items = signal.get_items();
+        let (result, done) = match (items.get(0), items.get(1)) {
+            (Some(&MessageItem::Str(ref result)), Some(&MessageItem::Bool(done))) => {
+                (result.as_str(), done)
+            },
+            _ => continue
+        };
+        match result {
+            "verify-match" => {
+                notify(Event::Matched);
+                return Ok(())
+            },
+            "verify-disconnected" => {
+                return Err("The fingerprint reader was disconnected".into())
+            },
+            "verify-unknown-error" => {
+                return Err("The fingerprint reader failed".into())
+            },
+            _ => notify(Event::Status(status_text(result).into()))
+        }
+        if result == "verify-no-match" {
+            notify(Event::NoMatch);
+        }
+        if done {
+            // fprintd stops after a final result, start over so the user
+            // can try again.
+            device.call("VerifyStop", vec![])?;
+            device.call("VerifyStart", vec![MessageItem::Str("any".into())])?;
+        }
+    }
+    Ok(())
+}
+
+/// What to tell the user about a `VerifyStatus` result.
+fn status_text(result: &str) -> &'static str {
+    match result {
+        "verify-no-match" => "Fingerprint not recognized, try again",
+        "verify-swipe-too-short" => "Swipe was too short, try again",
+        "verify-finger-not-centered" => "Center your finger on the reader and try again",
+        "verify-remove-and-retry" => "Remove your finger and try again",
+        _ => "Scan your finger again"
+    }
+}
+
+fn call(con: &Connection,
+        service: &str,
+        path: &str,
+        interface: &str,
+        method: &str,
+        args: Vec<MessageItem>) -> Result<Message, String> {
+    let mut msg = Message::new_method_call(service, path, interface, method)?;
+    for arg in args {
+        msg = msg.append(arg);
+    }
+    con.send_with_reply_and_block(msg, DBUS_WAIT_TIME)
+        .map_err(|err| format!("fprintd {} failed: {}", method, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::{BufRead, BufReader};
+    use std::process::{self, Child, Command, Stdio};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use dbus::{BusType, Connection, ConnectionItem, Message, MessageItem, Path};
+
+    use wakeup::Wakeup;
+
+    use super::{Config, Event, Fingerprint, DEVICE_INTERFACE, MANAGER_PATH};
+
+    const DEVICE_PATH: &'static str = "/net/reactivated/Fprint/Device/0";
+
+    /// A session bus of the test's own, so it doesn't need one to be running.
+    /// It's stopped when this is dropped.
+    struct PrivateBus(Child);
+
+    impl PrivateBus {
+        /// Starts the bus and points `BusType::Session` at it, or returns
+        /// `None` if `dbus-daemon` can't be run.
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(&["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|err| eprintln!("Skipping, could not run dbus-daemon: {}", err))
+                .ok()?;
+            let mut address = String::new();
+            let stdout = daemon.stdout.take().expect("dbus-daemon has no stdout");
+            BufReader::new(stdout).read_line(&mut address)
+                .expect("Could not read the address of dbus-daemon");
+            let bus = PrivateBus(daemon);
+            assert!(!address.trim().is_empty(), "dbus-daemon did not print an address");
+            env::set_var("DBUS_SESSION_BUS_ADDRESS", address.trim());
+            Some(bus)
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Stands in for fprintd on the session bus. The first scan doesn't
+    /// match, the second one does.
+    fn fake_fprintd(service: String, ready: Sender<()>) {
+        let con = Connection::get_private(BusType::Session)
+            .expect("Could not get d-bus connection");
+        con.register_name(&service, 0)
+            .expect("Could not take the service name");
+        con.register_object_path(MANAGER_PATH).unwrap();
+        con.register_object_path(DEVICE_PATH).unwrap();
+        ready.send(()).unwrap();
+        let mut scans = 0;
+        for item in con.iter(1000) {
+            let call = match item {
+                ConnectionItem::MethodCall(call) => call,
+                _ => continue
+            };
+            let (_, _, _, member) = call.headers();
+            let mut reply = Message::new_method_return(&call).unwrap();
+            let mut status = None;
+            match member.as_ref().map(|member| member.as_str()) {
+                Some("GetDefaultDevice") => {
+                    let device = Path::new(DEVICE_PATH).unwrap();
+                    reply = reply.append(MessageItem::ObjectPath(device));
+                },
+                Some("VerifyStart") => {
+                    scans += 1;
+                    status = Some(if scans == 1 { "verify-no-match" } else { "verify-match" });
+                },
+                Some("Release") => {
+                    con.send(reply).unwrap();
+                    return
+                },
+                _ => {}
+            }
+            con.send(reply).unwrap();
+            if let Some(status) = status {
+                let signal = Message::new_signal(DEVICE_PATH, DEVICE_INTERFACE,
+                                                 "VerifyStatus").unwrap()
+                    .append(MessageItem::Str(status.into()))
+                    .append(MessageItem::Bool(true));
+                con.send(signal).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn second_scan_matches() {
+        let _bus = match PrivateBus::start() {
+            Some(bus) => bus,
+            None => return
+        };
+        let service = format!("net.reactivated.Fprint.Test{}", process::id());
+        let (ready_sender, ready) = channel();
+        let fake_service = service.clone();
+        let fake = thread::spawn(move || fake_fprintd(fake_service, ready_sender));
+        ready.recv().unwrap();
+        let (_wakeup, waker) = Wakeup::new()
+            .expect("Could not create wakeup pipe");
+        let config = Config { bus: BusType::Session, service, .. Config::default() };
+        let fingerprint = Fingerprint::start(config, "alice".into(), waker);
+        let mut events = Vec::new();
+        let start = Instant::now();
+        while events.last() != Some(&Event::Matched) {
+            assert!(start.elapsed() < Duration::from_secs(5),
+                    "Never matched, got {:?}", events);
+            match fingerprint.next_event() {
+                Some(event) => events.push(event),
+                None => thread::sleep(Duration::from_millis(1))
+            }
+        }
+        assert_eq!(events, vec![
+            Event::Status("Scan your finger or type your password".into()),
+            Event::Status("Fingerprint not recognized, try again".into()),
+            Event::NoMatch,
+            Event::Matched
+        ]);
+        drop(fingerprint);
+        fake.join().unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+//! Hardens the process against leaking what it holds in memory, like the
+//! password being typed in or the screenshot of the unlocked session.
+//!
+//! None of these are fatal if they fail, since refusing to lock the screen
+//! would be worse, but each failure is warned about.
+
+use std::fmt;
+use std::io;
+
+use libc::{self, rlimit};
+
+/// Which protections are active.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Protections {
+    /// No core dump is written if the process crashes.
+    pub no_core_dumps: bool,
+    /// Other processes of the same user can't attach with ptrace or read
+    /// `/proc/<pid>/mem`.
+    pub no_ptrace: bool,
+    /// Memory that is already mapped can't be swapped out.
+    pub locked_current: bool,
+    /// Memory mapped from now on can't be swapped out either.
+    pub locked_future: bool
+}
+
+impl fmt::Display for Protections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |active| if active { "yes" } else { "NO" };
+        write!(f, "core dumps disabled: {}, ptrace disabled: {}, \
+                   memory locked: {}",
+               flag(self.no_core_dumps),
+               flag(self.no_ptrace),
+               match (self.locked_current, self.locked_future) {
+                   (true, true) => "yes",
+                   (true, false) => "current only",
+                   _ => "NO"
+               })
+    }
+}
+
+/// Applies every protection that is permitted.
+pub fn harden() -> Protections {
+    let mut protections = Protections::default();
+    match disable_core_dumps() {
+        Ok(()) => protections.no_core_dumps = true,
+        Err(err) => eprintln!("Warning: Could not disable core dumps: {}", err)
+    }
+    match disable_ptrace() {
+        Ok(()) => protections.no_ptrace = true,
+        Err(err) => eprintln!("Warning: Could not disable ptrace: {}", err)
+    }
+    let (current, future) = lock_memory();
+    protections.locked_current = current;
+    protections.locked_future = future;
+    protections
+}
+
+fn disable_core_dumps() -> io::Result<()> {
+    let limit = rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Also stops core dumps, even if the limit is raised again.
+fn disable_ptrace() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Locks all memory, returning whether current and future mappings are locked.
+///
+/// Future mappings are only locked if there's no limit on locked memory:
+/// otherwise allocations start failing once the limit is hit, and the
+/// screenshots alone can be larger than the usual limit.
+fn lock_memory() -> (bool, bool) {
+    let mut limit = rlimit { rlim_cur: 0, rlim_max: 0 };
+    let unlimited = unsafe {
+        libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) == 0
+    } && limit.rlim_cur == libc::RLIM_INFINITY;
+    if unlimited {
+        if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } == 0 {
+            return (true, true)
+        }
+        eprintln!("Warning: Could not lock memory: {}",
+                  io::Error::last_os_error());
+    } else {
+        eprintln!("Warning: Locked memory is limited (see `ulimit -l`), \
+                   only memory that is already mapped will be locked");
+    }
+    if unsafe { libc::mlockall(libc::MCL_CURRENT) } == 0 {
+        return (true, false)
+    }
+    eprintln!("Warning: Could not lock memory: {}", io::Error::last_os_error());
+    (false, false)
+}
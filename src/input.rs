@@ -2,57 +2,831 @@
 //!
 //! Module containing logic for writing to the screen.
 
-use std::ffi::{CStr, CString};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use wayland_client::EventQueueHandle;
 use wayland_client::protocol::wl_keyboard;
-use wayland_kbd::{self, ModifiersState, keysyms};
+use wayland_kbd::{ModifiersState, keysyms};
 
-use pam::check_auth;
-
-use libc::{getuid, getpwuid};
+use auth::{Authenticator, Event, Message, Transaction};
+use compose::{Compose, Composed};
+use controls::{Action, Control, Keypad};
+use fprint::{self, Fingerprint};
+use layout::Combo;
+use secret::{self, SecretBuffer};
+use throttle::Throttle;
+use totp::{self, Totp, UsedCodes};
+use wakeup::Waker;
 
 use ::color::Color;
 
+/// What the color of the screen is based on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Feedback {
+    /// Only what state authentication is in, and that a key was pressed.
+    /// Nothing about what was typed can be learned from it.
+    State,
+    /// A hash of the characters typed so far. Anyone who can see the screen
+    /// learns something about the password, so this has to be opted into.
+    Legacy
+}
+
+/// Background for when nothing is going on.
+const IDLE_COLOR: u32 = 0x202020;
+/// Backgrounds for typing, a different one is picked on every key press.
+const TYPING_COLORS: [u32; 4] = [0x1d3557, 0x264653, 0x2a3d66, 0x3a2f5b];
+/// Background for when a character was deleted.
+const DELETED_COLOR: u32 = 0x4a3a5a;
+/// Background for when a word was deleted.
+const DELETED_WORD_COLOR: u32 = 0x5a2a4a;
+/// Background for when what was typed was deleted.
+const CLEARED_COLOR: u32 = 0x5a4a1a;
+/// Background while the answer is being checked.
+const VERIFYING_COLOR: u32 = 0x1a5a3a;
+/// Background after a failed attempt.
+const WRONG_COLOR: u32 = 0x7a1a1a;
+
+/// What the screen shows when `Feedback::State` is used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ScreenState {
+    Idle,
+    /// Index into `TYPING_COLORS`.
+    Typing(usize),
+    Deleted,
+    DeletedWord,
+    Cleared,
+    Verifying,
+    Wrong
+}
+
+impl ScreenState {
+    fn color(self) -> Color {
+        let color = match self {
+            ScreenState::Idle => IDLE_COLOR,
+            ScreenState::Typing(index) => TYPING_COLORS[index],
+            ScreenState::Deleted => DELETED_COLOR,
+            ScreenState::DeletedWord => DELETED_WORD_COLOR,
+            ScreenState::Cleared => CLEARED_COLOR,
+            ScreenState::Verifying => VERIFYING_COLOR,
+            ScreenState::Wrong => WRONG_COLOR
+        };
+        Color::from_u8s((color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+}
+
+/// How many times a second a held down key repeats, until the compositor
+/// says otherwise.
+const DEFAULT_REPEAT_RATE: i32 = 25;
+/// How long a key has to be held down before it repeats, in milliseconds,
+/// until the compositor says otherwise.
+const DEFAULT_REPEAT_DELAY: i32 = 600;
+
+/// An editing key that is held down.
+#[derive(Clone, Copy, Debug)]
+struct Repeat {
+    keysym: u32,
+    /// What the key does every time it repeats.
+    edit: Edit,
+    /// When it repeats next.
+    next: Instant
+}
+
+/// What a key press did to what was typed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Edit {
+    Nothing,
+    Typed,
+    Deleted,
+    DeletedWord,
+    Cleared
+}
+
 pub struct Input {
     /// Buffer of what the user has input so far.
-    buffer: String,
-    /// Username, gotten from system during `new`.
-    username: String,
+    buffer: SecretBuffer,
+    /// The users that are allowed to unlock.
+    users: Vec<String>,
+    /// The user that has to authenticate to unlock, if one was picked.
+    ///
+    /// When more than one user is allowed, what is typed in goes into the
+    /// username field until one is picked.
+    username: Option<String>,
+    /// What checks the user's credentials, usually PAM.
+    authenticator: Arc<dyn Authenticator>,
     /// Boolean value saying if the user has logged in yet or not.
     logged_in: bool,
-    /// Number of failed login attempts.
-    failed: u32,
+    /// Keeps track of failed attempts, and how long until the next one.
+    throttle: Throttle,
+    /// Seconds left until the next attempt, as last shown on screen.
+    shown_countdown: Option<u64>,
+    /// The ongoing authentication attempt, if there is one.
+    transaction: Option<Transaction>,
+    /// Answer for the first prompt of the transaction, which is what the
+    /// user typed in before hitting enter.
+    first_answer: Option<SecretBuffer>,
+    /// The prompt PAM is waiting on an answer for.
+    prompt: Option<Message>,
+    /// Informational and error messages PAM sent during the last attempt.
+    messages: Vec<String>,
+    /// Where to find fprintd, if fingerprints can be used to unlock.
+    fingerprint_config: Option<fprint::Config>,
+    /// The fingerprint verification running alongside password entry.
+    fingerprint: Option<Fingerprint>,
+    /// What the fingerprint verification last had to say.
+    fingerprint_status: Option<String>,
+    /// Where the one-time code secrets are, if a code has to be entered
+    /// after the password.
+    totp_secrets: Option<totp::Secrets>,
+    /// What to check the one-time code against, once the first step passed.
+    pending_code: Option<Totp>,
+    /// The one-time codes that already let someone in, which can't be used
+    /// again.
+    used_codes: UsedCodes,
+    /// Turns dead keys and Compose sequences into the characters they make.
+    compose: Option<Compose>,
+    /// Whether Caps Lock is on.
+    caps_lock: bool,
+    /// Whether Num Lock is on.
+    num_lock: bool,
+    /// Whether the keypad types digits, even when Num Lock is off.
+    force_num_lock: bool,
+    /// Time between two repeats of a held down key, if keys repeat at all.
+    repeat_interval: Option<Duration>,
+    /// How long a key has to be held down before it starts repeating.
+    repeat_delay: Duration,
+    /// The editing key being held down, if it repeats.
+    repeat: Option<Repeat>,
+    /// Names of the layouts in the keymap, in the order of their groups.
+    layouts: Vec<String>,
+    /// The group of the layout keys are looked up in.
+    layout: u32,
+    /// What switches to the next layout, if anything does.
+    layout_switch: Option<Combo>,
+    /// The layout the user switched to on the lock screen, which keyboards
+    /// use instead of the compositor's.
+    forced_layout: Option<u32>,
+    /// The keypad drawn on screen, if there is one.
+    keypad: Option<Keypad>,
+    /// Whether the keypad types capital letters.
+    shifted: bool,
+    /// Used by authentication to wake up the main loop.
+    waker: Waker,
+    /// What the color of the screen is based on.
+    feedback: Feedback,
+    /// What the screen shows, for `Feedback::State`.
+    screen_state: ScreenState,
+    /// Whether the last attempt failed, and nothing was typed since.
+    failed: bool,
     /// The new color, if a new one needs to be generated.
     pub new_color: Option<Color>
 }
 
 impl Input {
-    pub fn new() -> Self {
-        let username = unsafe {
-            let uid = getuid();
-            let pwuid = getpwuid(uid);
-            let slice = CStr::from_ptr((*pwuid).pw_name);
-            slice.to_string_lossy().into_owned()
+    pub fn new(users: Vec<String>,
+               authenticator: Arc<dyn Authenticator>,
+               throttle: Throttle,
+               waker: Waker) -> Self {
+        assert!(!users.is_empty(), "At least one user has to be allowed");
+        let username = if users.len() == 1 {
+            Some(users[0].clone())
+        } else {
+            None
         };
         Input {
-            buffer: String::new(),
+            buffer: SecretBuffer::new(),
+            users,
             username,
+            authenticator,
             logged_in: false,
-            failed: 0,
+            throttle,
+            shown_countdown: None,
+            transaction: None,
+            first_answer: None,
+            prompt: None,
+            messages: Vec::new(),
+            fingerprint_config: None,
+            fingerprint: None,
+            fingerprint_status: None,
+            totp_secrets: None,
+            pending_code: None,
+            used_codes: UsedCodes::new(None),
+            compose: None,
+            caps_lock: false,
+            num_lock: false,
+            force_num_lock: false,
+            repeat_interval: None,
+            repeat_delay: Duration::from_secs(0),
+            repeat: None,
+            layouts: Vec::new(),
+            layout: 0,
+            layout_switch: None,
+            forced_layout: None,
+            keypad: None,
+            shifted: false,
+            waker,
+            feedback: Feedback::State,
+            screen_state: ScreenState::Idle,
+            failed: false,
             new_color: None
+        }.with_repeat_info(DEFAULT_REPEAT_RATE, DEFAULT_REPEAT_DELAY)
+    }
+
+    /// Lets the user unlock with their fingerprint too.
+    pub fn with_fingerprint(mut self, config: fprint::Config) -> Self {
+        self.fingerprint_config = Some(config);
+        self.start_fingerprint();
+        self
+    }
+
+    /// Asks for a one-time code after the password or fingerprint.
+    pub fn with_totp(mut self, secrets: totp::Secrets, used_codes: UsedCodes) -> Self {
+        self.totp_secrets = Some(secrets);
+        self.used_codes = used_codes;
+        self
+    }
+
+    /// Runs key presses through a compose table before typing them in.
+    pub fn with_compose(mut self, compose: Compose) -> Self {
+        self.compose = Some(compose);
+        self
+    }
+
+    /// Makes the keypad type digits, as if Num Lock was on.
+    ///
+    /// Clients can't change the lock state on Wayland, so Num Lock itself
+    /// is left alone.
+    pub fn with_forced_num_lock(mut self) -> Self {
+        self.force_num_lock = true;
+        self
+    }
+
+    /// Lets the user switch to the next layout by pressing `combo`.
+    pub fn with_layout_switch(mut self, combo: Combo) -> Self {
+        self.layout_switch = Some(combo);
+        self
+    }
+
+    /// Draws a keypad on screen, for when there's no keyboard.
+    pub fn with_keypad(mut self, keypad: Keypad) -> Self {
+        self.keypad = Some(keypad);
+        self
+    }
+
+    /// Changes what the color of the screen is based on.
+    pub fn with_feedback(mut self, feedback: Feedback) -> Self {
+        self.feedback = feedback;
+        self
+    }
+
+    /// Updates whether Caps Lock and Num Lock are on.
+    pub fn set_locks(&mut self, caps_lock: bool, num_lock: bool) {
+        if (caps_lock, num_lock) != (self.caps_lock, self.num_lock) {
+            self.caps_lock = caps_lock;
+            self.num_lock = num_lock;
+            // So the overlay is redrawn.
+            self.update_screen_color(Edit::Nothing);
         }
     }
 
+    /// Sets how fast held down editing keys repeat, as `wl_keyboard` describes
+    /// it: `rate` times a second, after `delay` milliseconds. A rate of 0
+    /// turns repeating off.
+    pub fn set_repeat_info(&mut self, rate: i32, delay: i32) {
+        self.repeat_interval = if rate > 0 {
+            // Rates above a billion would repeat without ever waiting.
+            let interval = Duration::new(0, 1_000_000_000 / rate as u32);
+            Some(interval.max(Duration::from_millis(1)))
+        } else {
+            None
+        };
+        self.repeat_delay = Duration::from_millis(delay.max(0) as u64);
+        if self.repeat_interval.is_none() {
+            self.repeat = None;
+        }
+    }
+
+    fn with_repeat_info(mut self, rate: i32, delay: i32) -> Self {
+        self.set_repeat_info(rate, delay);
+        self
+    }
+
+    /// Stops repeating the key that is held down, like when the keyboard
+    /// focus is lost and its release would never arrive.
+    pub fn stop_repeat(&mut self) {
+        self.repeat = None;
+    }
+
+    /// Updates the names of the layouts, when a new keymap was loaded.
+    pub fn set_layouts(&mut self, layouts: Vec<String>) {
+        if layouts != self.layouts {
+            if self.forced_layout.map_or(false, |layout| layout as usize >= layouts.len()) {
+                self.forced_layout = None;
+            }
+            self.layouts = layouts;
+            self.update_screen_color(Edit::Nothing);
+        }
+    }
+
+    /// Updates which layout keys are looked up in.
+    pub fn set_layout(&mut self, layout: u32) {
+        if layout != self.layout {
+            self.layout = layout;
+            self.update_screen_color(Edit::Nothing);
+        }
+    }
+
+    /// The layout the user switched to, if they did.
+    pub fn forced_layout(&self) -> Option<u32> {
+        self.forced_layout
+    }
+
+    /// Switches to the next layout, if there is more than one.
+    fn switch_layout(&mut self) {
+        if self.layouts.len() > 1 {
+            let layout = (self.layout + 1) % self.layouts.len() as u32;
+            self.forced_layout = Some(layout);
+            self.set_layout(layout);
+        }
+    }
+
+    /// The rows of controls that should be drawn on the lock screen, from
+    /// top to bottom.
+    pub fn controls(&self) -> Vec<Vec<Control>> {
+        let mut rows = match self.keypad {
+            Some(keypad) => keypad.rows(self.shifted),
+            None => Vec::new()
+        };
+        let mut buttons = vec![Control::new("Unlock", Action::Submit),
+                               Control::new("Clear", Action::Clear)];
+        if self.layouts.len() > 1 {
+            buttons.push(Control::new("Layout", Action::SwitchLayout));
+        }
+        rows.push(buttons);
+        rows
+    }
+
+    /// Does what the control that was clicked does.
+    pub fn activate(&mut self, action: Action) {
+        let edit = match action {
+            Action::Submit => {
+                self.submit();
+                Edit::Nothing
+            },
+            Action::Clear => self.edit(Edit::Cleared),
+            Action::Delete => self.edit(Edit::Deleted),
+            Action::Type(chr) => {
+                let mut bytes = [0; 4];
+                self.buffer.push_str(chr.encode_utf8(&mut bytes));
+                secret::zero(&mut bytes);
+                Edit::Typed
+            },
+            Action::Shift => {
+                self.shifted = !self.shifted;
+                Edit::Nothing
+            },
+            Action::SwitchLayout => {
+                self.switch_layout();
+                return
+            }
+        };
+        if edit != Edit::Nothing {
+            self.failed = false;
+        }
+        self.update_screen_color(edit);
+    }
+
     /// Determines if the user has succesfully logged in yet.
     pub fn is_logged_in(&self) -> bool {
         self.logged_in
     }
 
-    /// Update the color of the screen.
-    pub fn update_screen_color(&mut self) {
+    /// Determines if what the user types is kept out of swap.
+    pub fn is_buffer_locked(&self) -> bool {
+        self.buffer.is_locked()
+    }
+
+    /// Determines if PAM is busy checking what the user has answered.
+    pub fn is_verifying(&self) -> bool {
+        self.transaction.is_some() && self.prompt.is_none()
+    }
+
+    /// Seconds left until the user is allowed to try again, if they have
+    /// to wait.
+    fn countdown(&self) -> Option<u64> {
+        self.throttle.remaining().map(|remaining| {
+            remaining.as_secs() + if remaining.subsec_nanos() > 0 { 1 } else { 0 }
+        })
+    }
+
+    /// How long the main loop can sleep before `poll` has to be called
+    /// again, if there's a countdown on screen or a key repeating.
+    pub fn timeout(&self) -> Option<Duration> {
+        let countdown = self.throttle.remaining().map(|remaining| {
+            match remaining.subsec_nanos() {
+                0 => Duration::from_secs(1),
+                nanos => Duration::new(0, nanos)
+            }
+        });
+        let repeat = self.repeat.map(|repeat| {
+            let now = Instant::now();
+            if repeat.next > now {
+                repeat.next - now
+            } else {
+                Duration::from_secs(0)
+            }
+        });
+        match (countdown, repeat) {
+            (Some(countdown), Some(repeat)) => Some(countdown.min(repeat)),
+            (countdown, repeat) => countdown.or(repeat)
+        }
+    }
+
+    /// The lines of text that should be drawn on the lock screen.
+    pub fn overlay(&self) -> Vec<String> {
+        let mut lines = self.messages.clone();
+        if let Some(ref status) = self.fingerprint_status {
+            lines.push(status.clone());
+        }
+        if self.is_verifying() {
+            lines.push("Verifying...".into());
+        }
+        if let Some(countdown) = self.countdown() {
+            if self.throttle.is_locked_out() {
+                lines.push(format!("Too many failed attempts, locked for {}:{:02}",
+                                   countdown / 60, countdown % 60));
+            } else {
+                lines.push(format!("Try again in {}s", countdown));
+            }
+        }
+        match self.username {
+            None => lines.push(format!("Username: {}", self.buffer.as_str())),
+            Some(ref username) if self.users.len() > 1 => {
+                lines.push(format!("Unlocking as {}", username))
+            },
+            Some(_) => {}
+        }
+        match self.prompt {
+            Some(Message::Prompt { ref text, echo: true }) => {
+                lines.push(format!("{}{}", text, self.buffer.as_str()))
+            },
+            Some(Message::Prompt { ref text, echo: false }) => {
+                lines.push(text.clone())
+            },
+            _ => {}
+        }
+        if let Some(name) = self.layouts.get(self.layout as usize) {
+            lines.push(format!("Layout: {}", name));
+        }
+        if self.caps_lock {
+            lines.push("Warning: Caps Lock is on".into());
+        }
+        if self.num_lock {
+            lines.push("Num Lock is on".into());
+        } else if self.force_num_lock {
+            lines.push("Keypad types digits".into());
+        }
+        lines
+    }
+
+    /// Handles everything authentication sent since the last time this
+    /// was called. Should be called whenever the main loop is woken up.
+    pub fn poll(&mut self) {
+        let mut changed = self.transaction.is_some() && self.converse();
+        changed |= self.poll_fingerprint();
+        let countdown = self.countdown();
+        if countdown != self.shown_countdown {
+            self.shown_countdown = countdown;
+            changed = true;
+        }
+        if changed {
+            self.update_screen_color(Edit::Nothing);
+        }
+        self.repeat_key();
+    }
+
+    /// Repeats the editing key that is held down, as many times as it
+    /// should have since the last time.
+    fn repeat_key(&mut self) {
+        let now = Instant::now();
+        while let Some(repeat) = self.repeat {
+            if repeat.next > now {
+                break
+            }
+            let interval = match self.repeat_interval {
+                Some(interval) => interval,
+                None => break
+            };
+            if self.buffer.is_empty() {
+                // Don't go on to the username field.
+                self.repeat = None;
+                break
+            }
+            let edit = self.edit(repeat.edit);
+            self.update_screen_color(edit);
+            self.repeat = Some(Repeat { next: repeat.next + interval, .. repeat });
+        }
+    }
+
+    /// Sends what the user typed to PAM, starting a new authentication
+    /// attempt if one isn't already waiting for an answer.
+    fn submit(&mut self) {
+        if self.is_verifying() {
+            // Wait until PAM actually asks for something.
+            return
+        }
+        if let Some(totp) = self.pending_code.take() {
+            self.check_code(totp);
+            return
+        }
+        if self.username.is_none() {
+            self.pick_user();
+            return
+        }
+        if self.transaction.is_none() && self.throttle.remaining().is_some() {
+            // Not allowed to try again yet. What they typed is kept, so they
+            // don't have to type it again.
+            return
+        }
+        let answer = mem::replace(&mut self.buffer, SecretBuffer::new());
+        self.prompt = None;
+        self.failed = false;
+        match self.transaction {
+            Some(ref transaction) => transaction.answer(answer),
+            None => {
+                self.messages.clear();
+                self.first_answer = Some(answer);
+                let username = self.username.clone()
+                    .expect("Started authenticating without a user");
+                self.transaction = Some(Transaction::start(self.authenticator.clone(),
+                                                           username,
+                                                           self.waker.clone()));
+            }
+        }
+        self.converse();
+    }
+
+    /// Takes what was typed into the username field as the user to unlock
+    /// as, if they are allowed to.
+    fn pick_user(&mut self) {
+        self.messages.clear();
+        let typed = self.buffer.as_str().to_owned();
+        self.buffer.clear();
+        if self.users.contains(&typed) {
+            self.username = Some(typed);
+            self.start_fingerprint();
+        } else {
+            self.messages.push(format!("{} is not allowed to unlock", typed));
+        }
+    }
+
+    /// Goes back to the username field, if there is one.
+    fn unpick_user(&mut self) {
+        if self.users.len() > 1 && self.transaction.is_none() {
+            self.username = None;
+            self.fingerprint = None;
+            self.fingerprint_status = None;
+            self.pending_code = None;
+            self.prompt = None;
+        }
+    }
+
+    /// Starts verifying the fingerprint of the picked user, if fingerprints
+    /// are enabled.
+    fn start_fingerprint(&mut self) {
+        let fingerprint = match (self.fingerprint_config.as_ref(), self.username.as_ref()) {
+            (Some(config), Some(username)) => {
+                Fingerprint::start(config.clone(), username.clone(), self.waker.clone())
+            },
+            _ => return
+        };
+        self.fingerprint = Some(fingerprint);
+    }
+
+    /// Handles everything the fingerprint verification sent.
+    ///
+    /// Returns true if anything changed.
+    fn poll_fingerprint(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let event = match self.fingerprint {
+                Some(ref fingerprint) => fingerprint.next_event(),
+                None => None
+            };
+            let event = match event {
+                Some(event) => event,
+                None => return changed
+            };
+            changed = true;
+            self.fingerprint_event(event);
+        }
+    }
+
+    /// Handles one thing the fingerprint verification sent.
+    fn fingerprint_event(&mut self, event: fprint::Event) {
+        match event {
+            fprint::Event::Status(text) => self.fingerprint_status = Some(text),
+            // The wait applies to fingerprints too, or they would get around
+            // it, and it's not their fault if they scan while waiting.
+            fprint::Event::NoMatch if self.throttle.remaining().is_some() => {},
+            fprint::Event::NoMatch => {
+                self.throttle.record_failure();
+                self.failed = true;
+                println!("Failed login attempt {}: fingerprint not recognized",
+                         self.throttle.failures());
+            },
+            fprint::Event::Matched => {
+                // Verification is over once the finger matched.
+                self.fingerprint = None;
+                self.fingerprint_status = None;
+                if self.throttle.remaining().is_some() {
+                    self.start_fingerprint();
+                    return
+                }
+                // The account still has to be allowed in, like after a
+                // password. Whatever was being answered is given up on.
+                let username = self.username.clone()
+                    .expect("Verified a fingerprint without a user");
+                self.first_answer = None;
+                self.prompt = None;
+                self.messages.clear();
+                self.transaction = Some(Transaction::admit(self.authenticator.clone(),
+                                                           username,
+                                                           self.waker.clone()));
+                self.converse();
+            },
+            fprint::Event::Unavailable(err) => {
+                eprintln!("Fingerprint authentication unavailable: {}", err);
+                self.fingerprint = None;
+                self.fingerprint_status = None;
+            }
+        }
+    }
+
+    /// Lets the user in, unless they still have to enter a one-time code.
+    fn first_step_passed(&mut self) {
+        let secrets = match self.totp_secrets {
+            Some(ref secrets) => secrets,
+            None => {
+                self.throttle.record_success();
+                self.logged_in = true;
+                return
+            }
+        };
+        let username = self.username.as_ref()
+            .expect("Authenticated without a user");
+        match secrets.load(username) {
+            Ok(totp) => {
+                self.pending_code = Some(totp);
+                self.prompt = Some(Message::Prompt {
+                    text: "Verification code: ".into(),
+                    echo: false
+                });
+            },
+            Err(err) => {
+                // Without the secret there's no way to check the code, so
+                // the user can't get in.
+                eprintln!("{}", err);
+                self.messages.push(err.to_string());
+                self.failed = true;
+            }
+        }
+    }
+
+    /// Checks the one-time code the user typed in.
+    fn check_code(&mut self, totp: Totp) {
+        let code = mem::replace(&mut self.buffer, SecretBuffer::new());
+        self.prompt = None;
+        self.messages.clear();
+        let username = self.username.clone()
+            .expect("Authenticated without a user");
+        let last_used = self.used_codes.last(&username);
+        if let Some(step) = totp.verify(code.as_str(), last_used) {
+            self.used_codes.record(&username, step);
+            self.throttle.record_success();
+            self.logged_in = true;
+        } else {
+            self.throttle.record_failure();
+            self.failed = true;
+            println!("Failed login attempt {}: wrong verification code",
+                     self.throttle.failures());
+            self.messages.push("Wrong verification code".into());
+            // The user has to get past the first step again, and the
+            // fingerprint can't be used for that if it already matched.
+            if self.fingerprint.is_none() {
+                self.start_fingerprint();
+            }
+        }
+    }
+
+    /// Carries on the conversation with PAM until it either finishes,
+    /// asks a question the user needs to answer, or has nothing new to say.
+    ///
+    /// Returns true if anything changed.
+    fn converse(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let event = match self.transaction {
+                Some(ref transaction) => transaction.next_event(),
+                None => None
+            };
+            let event = match event {
+                Some(event) => event,
+                None => return changed
+            };
+            changed = true;
+            match event {
+                Event::Message(prompt @ Message::Prompt { .. }) => {
+                    match self.first_answer.take() {
+                        Some(answer) => {
+                            if let Some(ref transaction) = self.transaction {
+                                transaction.answer(answer)
+                            }
+                        },
+                        None => {
+                            self.prompt = Some(prompt);
+                            return changed
+                        }
+                    }
+                },
+                Event::Message(Message::Info(text)) |
+                Event::Message(Message::Error(text)) => {
+                    self.messages.push(text)
+                },
+                Event::Done(result) => {
+                    self.transaction = None;
+                    self.first_answer = None;
+                    match result {
+                        Ok(()) => self.first_step_passed(),
+                        Err(err) => {
+                            if err.is_failed_attempt() {
+                                self.throttle.record_failure();
+                                println!("Failed login attempt {}: {}",
+                                         self.throttle.failures(), err);
+                            } else {
+                                // Not the user's fault, like a broken PAM
+                                // configuration, so they don't have to wait.
+                                eprintln!("Could not authenticate: {}", err);
+                            }
+                            self.failed = true;
+                            self.messages.push(err.to_string());
+                            // The fingerprint stops once it matched.
+                            if self.fingerprint.is_none() {
+                                self.start_fingerprint();
+                            }
+                        }
+                    }
+                    return changed
+                }
+            }
+        }
+    }
+
+    /// Update the color of the screen, after what was typed was edited.
+    fn update_screen_color(&mut self, edit: Edit) {
+        self.screen_state = self.next_screen_state(edit);
+        let color = match self.feedback {
+            Feedback::State => self.screen_state.color(),
+            Feedback::Legacy => self.legacy_color()
+        };
+        self.new_color = Some(color);
+    }
+
+    fn next_screen_state(&self, edit: Edit) -> ScreenState {
+        if self.is_verifying() {
+            return ScreenState::Verifying
+        }
+        if self.failed {
+            return ScreenState::Wrong
+        }
+        match edit {
+            Edit::Typed => {
+                // Never the same twice in a row, so every key press shows.
+                let previous = match self.screen_state {
+                    ScreenState::Typing(index) => index,
+                    _ => 0
+                };
+                let step = 1 + ::rand::random::<usize>() % (TYPING_COLORS.len() - 1);
+                ScreenState::Typing((previous + step) % TYPING_COLORS.len())
+            },
+            Edit::Deleted if self.buffer.is_empty() => ScreenState::Cleared,
+            Edit::Deleted => ScreenState::Deleted,
+            Edit::DeletedWord => ScreenState::DeletedWord,
+            Edit::Cleared => ScreenState::Cleared,
+            Edit::Nothing if self.buffer.is_empty() => ScreenState::Idle,
+            Edit::Nothing => match self.screen_state {
+                ScreenState::Typing(_) |
+                ScreenState::Deleted |
+                ScreenState::DeletedWord => self.screen_state,
+                _ => ScreenState::Typing(0)
+            }
+        }
+    }
+
+    /// The color for `Feedback::Legacy`, a hash of what was typed.
+    fn legacy_color(&self) -> Color {
         let (mut r, mut g, mut b) = (0u8, 0u8, 0u8);
-        for chr in self.buffer.chars() {
+        for chr in self.buffer.as_str().chars() {
             let val = unsafe {
                 let val = chr as u32;
                 let bytes: [u8; 4] = ::std::mem::transmute(val.to_be());
@@ -62,53 +836,708 @@ impl Input {
             b = b.wrapping_add(val / 2);
             g = g.wrapping_add(val / 3);
         }
-        let color = Color::from_u8s(r, g, b);
-        self.new_color = Some(color);
-
+        Color::from_u8s(r, g, b)
     }
-}
 
-
-impl wayland_kbd::Handler for Input {
-    fn key(&mut self,
-           _: &mut EventQueueHandle,
-           _: &wl_keyboard::WlKeyboard,
-           _: u32,
-           _: u32,
-           _: &ModifiersState,
-           _: u32,
-           keysym: u32,
-           state: wl_keyboard::KeyState,
-           text: Option<String>) {
+    /// Handles a key going up or down.
+    pub fn handle_key(&mut self,
+                      keysym: u32,
+                      modifiers: &ModifiersState,
+                      state: wl_keyboard::KeyState,
+                      text: Option<String>) {
+        let (keysym, text) = match keypad_digit(keysym) {
+            Some((digit_keysym, digit)) if self.force_num_lock && !self.num_lock => {
+                (digit_keysym, Some(digit.to_string()))
+            },
+            _ => (keysym, text)
+        };
         if let wl_keyboard::KeyState::Pressed = state {
-            match keysym {
-                keysyms::XKB_KEY_Return |
-                keysyms::XKB_KEY_KP_Enter => {
-                    let check_auth = unsafe {
-                        let username = CString::new(self.username.as_str())
-                            .expect("Username could not be C-String-ed");
-                        let password = CString::new(self.buffer.as_str())
-                            .expect("Password could not be C-String-ed");
-                        check_auth(username.as_ptr(), password.as_ptr())
-                    };
-                    if check_auth {
-                        self.logged_in = true;
-                    } else {
-                        self.failed += 1;
-                        println!("Failed login attempt {}", self.failed);
-                    }
-                    self.buffer.clear()
+            if self.layout_switch.map_or(false, |combo| combo.matches(keysym, modifiers)) {
+                // Only changes how keys are looked up, nothing is typed.
+                self.switch_layout();
+                if let Some(text) = text {
+                    secret::wipe(text);
+                }
+                return
+            }
+            let composed = match self.compose {
+                Some(ref mut compose) if modifiers.ctrl || modifiers.alt || modifiers.logo => {
+                    compose.reset();
+                    Composed::Nothing
                 },
-                keysyms::XKB_KEY_BackSpace => {
-                    self.buffer.pop();
+                Some(ref mut compose) => compose.feed(keysym),
+                None => Composed::Nothing
+            };
+            let edit = if let Composed::Text(text) = composed {
+                self.buffer.push_str(text.as_str());
+                secret::wipe(text);
+                Edit::Typed
+            } else if composed != Composed::Nothing {
+                // Part of a sequence that isn't done, or was cancelled.
+                Edit::Nothing
+            } else if modifiers.ctrl {
+                match keysym {
+                    keysyms::XKB_KEY_u |
+                    keysyms::XKB_KEY_U => self.edit(Edit::Cleared),
+                    keysyms::XKB_KEY_w |
+                    keysyms::XKB_KEY_W |
+                    keysyms::XKB_KEY_BackSpace => self.edit(Edit::DeletedWord),
+                    _ => Edit::Nothing
                 }
-                _ => {
-                    if let Some(text) = text {
-                        self.buffer.push_str(text.as_str());
+            } else if (modifiers.alt || modifiers.logo) && text.is_none() {
+                // Shortcuts, not something the user meant to type. Some
+                // layouts type characters with Alt though.
+                Edit::Nothing
+            } else {
+                match keysym {
+                    keysyms::XKB_KEY_Return |
+                    keysyms::XKB_KEY_KP_Enter => {
+                        self.activate(Action::Submit);
+                        Edit::Nothing
+                    },
+                    keysyms::XKB_KEY_BackSpace => self.edit(Edit::Deleted),
+                    keysyms::XKB_KEY_Escape => self.edit(Edit::Cleared),
+                    _ => match text {
+                        // Keys like Tab or Delete come with control characters.
+                        Some(ref text) if !text.chars().any(char::is_control) => {
+                            self.buffer.push_str(text.as_str());
+                            Edit::Typed
+                        },
+                        _ => Edit::Nothing
                     }
                 }
+            };
+            if edit != Edit::Nothing {
+                self.failed = false;
+                self.update_screen_color(edit);
             }
-            self.update_screen_color();
+            self.repeat = match edit {
+                // Only editing keys repeat, holding down a character types
+                // it once.
+                Edit::Deleted | Edit::DeletedWord if self.repeat_interval.is_some() => {
+                    Some(Repeat {
+                        keysym,
+                        edit,
+                        next: Instant::now() + self.repeat_delay
+                    })
+                },
+                _ => None
+            };
+        } else if self.repeat.map_or(false, |repeat| repeat.keysym == keysym) {
+            self.repeat = None;
+        }
+        if let Some(text) = text {
+            secret::wipe(text);
+        }
+    }
+
+    /// Deletes from what was typed, returning what was done.
+    fn edit(&mut self, edit: Edit) -> Edit {
+        match edit {
+            Edit::Deleted => {
+                if !self.buffer.pop() {
+                    self.unpick_user();
+                }
+            },
+            Edit::DeletedWord => self.buffer.pop_word(),
+            Edit::Cleared => self.buffer.clear(),
+            Edit::Typed | Edit::Nothing => {}
+        }
+        edit
+    }
+}
+
+/// The digit a keypad key types when Num Lock is on, and its keysym.
+fn keypad_digit(keysym: u32) -> Option<(u32, char)> {
+    Some(match keysym {
+        keysyms::XKB_KEY_KP_Insert => (keysyms::XKB_KEY_KP_0, '0'),
+        keysyms::XKB_KEY_KP_End => (keysyms::XKB_KEY_KP_1, '1'),
+        keysyms::XKB_KEY_KP_Down => (keysyms::XKB_KEY_KP_2, '2'),
+        keysyms::XKB_KEY_KP_Next => (keysyms::XKB_KEY_KP_3, '3'),
+        keysyms::XKB_KEY_KP_Left => (keysyms::XKB_KEY_KP_4, '4'),
+        keysyms::XKB_KEY_KP_Begin => (keysyms::XKB_KEY_KP_5, '5'),
+        keysyms::XKB_KEY_KP_Right => (keysyms::XKB_KEY_KP_6, '6'),
+        keysyms::XKB_KEY_KP_Home => (keysyms::XKB_KEY_KP_7, '7'),
+        keysyms::XKB_KEY_KP_Up => (keysyms::XKB_KEY_KP_8, '8'),
+        keysyms::XKB_KEY_KP_Prior => (keysyms::XKB_KEY_KP_9, '9'),
+        keysyms::XKB_KEY_KP_Delete => (keysyms::XKB_KEY_KP_Decimal, '.'),
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use wayland_client::protocol::wl_keyboard::KeyState;
+    use wayland_kbd::{keysyms, ModifiersState};
+
+    use auth::mock::MockAuthenticator;
+    use compose::Compose;
+    use controls::{Action, Control, Keypad};
+    use fprint;
+    use pam::PamError;
+    use throttle::{Policy, Throttle};
+    use totp::{Secrets, UsedCodes};
+    use totp::fixtures::TestDir;
+    use wakeup::Wakeup;
+
+    use super::Input;
+
+    /// Lets the user try again right away.
+    const NO_DELAY: Policy = Policy {
+        base_delay: Duration::from_secs(0),
+        max_delay: Duration::from_secs(0),
+        lockout_after: 0,
+        lockout_duration: Duration::from_secs(0)
+    };
+
+    fn input(authenticator: MockAuthenticator) -> (Input, Wakeup) {
+        input_with_policy(authenticator, NO_DELAY)
+    }
+
+    fn input_with_policy(authenticator: MockAuthenticator,
+                         policy: Policy) -> (Input, Wakeup) {
+        let (wakeup, waker) = Wakeup::new()
+            .expect("Could not create wakeup pipe");
+        let input = Input::new(vec!["alice".into()],
+                               Arc::new(authenticator),
+                               Throttle::new(policy, None),
+                               waker);
+        (input, wakeup)
+    }
+
+    const NO_MODIFIERS: ModifiersState = ModifiersState {
+        ctrl: false,
+        alt: false,
+        shift: false,
+        caps_lock: false,
+        logo: false,
+        num_lock: false
+    };
+
+    const CTRL: ModifiersState = ModifiersState { ctrl: true, .. NO_MODIFIERS };
+
+    fn type_text(input: &mut Input, text: &str) {
+        for chr in text.chars() {
+            input.handle_key(chr as u32, &NO_MODIFIERS, KeyState::Pressed,
+                             Some(chr.to_string()));
+            input.handle_key(chr as u32, &NO_MODIFIERS, KeyState::Released, None);
+        }
+    }
+
+    fn press(input: &mut Input, keysym: u32) {
+        press_with(input, keysym, &NO_MODIFIERS, None);
+    }
+
+    fn press_with(input: &mut Input,
+                  keysym: u32,
+                  modifiers: &ModifiersState,
+                  text: Option<&str>) {
+        input.handle_key(keysym, modifiers, KeyState::Pressed, text.map(String::from));
+        input.handle_key(keysym, modifiers, KeyState::Released, None);
+    }
+
+    /// Polls until the authenticator has something for the user.
+    fn wait(input: &mut Input) {
+        let start = Instant::now();
+        while input.is_verifying() {
+            assert!(start.elapsed() < Duration::from_secs(5),
+                    "Authentication never finished");
+            thread::sleep(Duration::from_millis(1));
+            input.poll();
+        }
+    }
+
+    #[test]
+    fn correct_password_unlocks() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (mut input, _wakeup) = input(mock);
+        type_text(&mut input, "hunter2");
+        assert!(!input.is_logged_in());
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+        assert_eq!(input.throttle.failures(), 0);
+    }
+
+    #[test]
+    fn wrong_password_is_counted() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (mut input, _wakeup) = input(mock);
+        for attempt in 1..4 {
+            type_text(&mut input, "hunter3");
+            press(&mut input, keysyms::XKB_KEY_KP_Enter);
+            wait(&mut input);
+            assert!(!input.is_logged_in());
+            assert_eq!(input.throttle.failures(), attempt);
+            assert!(input.buffer.is_empty());
+        }
+        assert!(input.overlay().contains(&"Authentication failed".to_string()));
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn broken_configuration_is_not_counted() {
+        let mock = MockAuthenticator::new().with_error(PamError::ServiceError);
+        let (mut input, _wakeup) = input_with_policy(mock, Policy {
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+            .. NO_DELAY
+        });
+        for _ in 0..2 {
+            type_text(&mut input, "hunter2");
+            press(&mut input, keysyms::XKB_KEY_Return);
+            wait(&mut input);
+        }
+        assert_eq!(input.throttle.remaining(), None);
+        assert_eq!(input.throttle.failures(), 0);
+        assert!(input.overlay().contains(&"PAM service error".to_string()));
+    }
+
+    #[test]
+    fn backspace_edits_password() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (mut input, _wakeup) = input(mock);
+        type_text(&mut input, "hunter23");
+        press(&mut input, keysyms::XKB_KEY_BackSpace);
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn multiple_prompts_are_answered_in_turn() {
+        let mock = MockAuthenticator::new()
+            .with_user("alice", &["hunter2", "123456"]);
+        let (mut input, _wakeup) = input(mock);
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(!input.is_logged_in());
+        assert_eq!(input.overlay(), vec!["Answer 2: ".to_string()]);
+        type_text(&mut input, "123456");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn unknown_user_is_not_let_in() {
+        let mock = MockAuthenticator::new().with_user("bob", &["hunter2"]);
+        let (mut input, _wakeup) = input(mock);
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(!input.is_logged_in());
+        assert_eq!(input.throttle.failures(), 1);
+    }
+
+    #[test]
+    fn fingerprint_match_checks_the_account() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (mut allowed, _wakeup) = input(mock);
+        allowed.fingerprint_event(fprint::Event::Matched);
+        wait(&mut allowed);
+        assert!(allowed.is_logged_in());
+
+        let mock = MockAuthenticator::new()
+            .with_user("alice", &["hunter2"])
+            .with_expired_account("alice");
+        let (mut expired, _wakeup) = input(mock);
+        expired.fingerprint_event(fprint::Event::Matched);
+        wait(&mut expired);
+        assert!(!expired.is_logged_in());
+        assert!(expired.overlay().contains(&"Account has expired".to_string()));
+    }
+
+    #[test]
+    fn unrecognized_fingerprints_are_counted() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let policy = Policy {
+            lockout_after: 2,
+            lockout_duration: Duration::from_secs(3600),
+            .. NO_DELAY
+        };
+        let (mut input, _wakeup) = input_with_policy(mock, policy);
+        for _ in 0..3 {
+            input.fingerprint_event(fprint::Event::NoMatch);
+        }
+        assert!(input.throttle.is_locked_out());
+        // Scans while locked out don't count, and don't get around it.
+        assert_eq!(input.throttle.failures(), 2);
+        input.fingerprint_event(fprint::Event::Matched);
+        assert!(!input.is_verifying());
+        assert!(!input.is_logged_in());
+    }
+
+    #[test]
+    fn username_is_asked_for_with_multiple_users() {
+        let mock = MockAuthenticator::new()
+            .with_user("alice", &["hunter2"])
+            .with_user("admin", &["letmein"]);
+        let (_wakeup, waker) = Wakeup::new()
+            .expect("Could not create wakeup pipe");
+        let mut input = Input::new(vec!["alice".into(), "admin".into()],
+                                   Arc::new(mock),
+                                   Throttle::new(NO_DELAY, None),
+                                   waker);
+        type_text(&mut input, "mallory");
+        assert_eq!(input.overlay(), vec!["Username: mallory".to_string()]);
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert!(!input.is_verifying());
+        assert!(input.overlay().contains(&"mallory is not allowed to unlock".to_string()));
+        type_text(&mut input, "alice");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert_eq!(input.overlay(), vec!["Unlocking as alice".to_string()]);
+        // Backspace on an empty password goes back to the username.
+        press(&mut input, keysyms::XKB_KEY_BackSpace);
+        type_text(&mut input, "admin");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        type_text(&mut input, "letmein");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn verification_code_is_required() {
+        let dir = TestDir::new("input");
+        dir.write("alice", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o600);
+        let secrets = Secrets::Dir(dir.0.clone());
+        let code = current_code(&secrets);
+        let wrong_code = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (input, _wakeup) = input(mock);
+        let mut input = input.with_totp(secrets, UsedCodes::new(None));
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(!input.is_logged_in());
+        assert_eq!(input.overlay(), vec!["Verification code: ".to_string()]);
+        type_text(&mut input, &wrong_code);
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert!(!input.is_logged_in());
+        assert_eq!(input.throttle.failures(), 1);
+        // Both steps have to be done again.
+        type_text(&mut input, &code);
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(!input.is_logged_in());
+        assert_eq!(input.throttle.failures(), 2);
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        type_text(&mut input, &code);
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert!(input.is_logged_in());
+    }
+
+    /// The code the user's authenticator app shows right now.
+    fn current_code(secrets: &Secrets) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        format!("{:06}", secrets.load("alice").unwrap().code_at(now))
+    }
+
+    #[test]
+    fn verification_code_is_only_good_once() {
+        let dir = TestDir::new("input-used");
+        dir.write("alice", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o600);
+        let secrets = Secrets::Dir(dir.0.clone());
+        let used_codes = dir.0.join("used-codes");
+        let code = current_code(&secrets);
+        // Like the next lock screen, after the code unlocked this one.
+        for &unlocks in &[true, false] {
+            let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+            let (input, _wakeup) = input(mock);
+            let mut input = input.with_totp(secrets.clone(),
+                                            UsedCodes::new(Some(used_codes.clone())));
+            type_text(&mut input, "hunter2");
+            press(&mut input, keysyms::XKB_KEY_Return);
+            wait(&mut input);
+            type_text(&mut input, &code);
+            press(&mut input, keysyms::XKB_KEY_Return);
+            assert_eq!(input.is_logged_in(), unlocks);
+        }
+    }
+
+    #[test]
+    fn screen_state_does_not_depend_on_what_is_typed() {
+        use super::ScreenState;
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let (mut input, _wakeup) = input(mock);
+        let mut previous = input.screen_state;
+        for chr in "aaaa".chars() {
+            type_text(&mut input, &chr.to_string());
+            match input.screen_state {
+                ScreenState::Typing(_) => {},
+                state => panic!("Typing showed {:?}", state)
+            }
+            assert!(input.screen_state != previous, "Key press did not show");
+            previous = input.screen_state;
+        }
+        for _ in 0..4 {
+            press(&mut input, keysyms::XKB_KEY_BackSpace);
+        }
+        assert_eq!(input.screen_state, ScreenState::Cleared);
+        type_text(&mut input, "hunter3");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert_eq!(input.screen_state, ScreenState::Verifying);
+        wait(&mut input);
+        assert_eq!(input.screen_state, ScreenState::Wrong);
+        type_text(&mut input, "h");
+        match input.screen_state {
+            ScreenState::Typing(_) => {},
+            state => panic!("Typing after a failure showed {:?}", state)
+        }
+    }
+
+    #[test]
+    fn editing_keys() {
+        use super::ScreenState;
+        let mock = MockAuthenticator::new().with_user("alice", &["correct horse"]);
+        let (mut input, _wakeup) = input(mock);
+        type_text(&mut input, "correct horse battery");
+        press_with(&mut input, keysyms::XKB_KEY_w, &CTRL, Some("\u{17}"));
+        assert_eq!(input.buffer.as_str(), "correct horse ");
+        assert_eq!(input.screen_state, ScreenState::DeletedWord);
+        type_text(&mut input, "staple  ");
+        press_with(&mut input, keysyms::XKB_KEY_BackSpace, &CTRL, None);
+        assert_eq!(input.buffer.as_str(), "correct horse ");
+        press(&mut input, keysyms::XKB_KEY_BackSpace);
+        assert_eq!(input.screen_state, ScreenState::Deleted);
+        press(&mut input, keysyms::XKB_KEY_Escape);
+        assert!(input.buffer.is_empty());
+        assert_eq!(input.screen_state, ScreenState::Cleared);
+        type_text(&mut input, "oops");
+        press_with(&mut input, keysyms::XKB_KEY_u, &CTRL, Some("\u{15}"));
+        assert!(input.buffer.is_empty());
+        // Shortcuts and keys that only come with control characters are
+        // not typed in.
+        let alt = ModifiersState { alt: true, .. NO_MODIFIERS };
+        press_with(&mut input, keysyms::XKB_KEY_F4, &alt, None);
+        press_with(&mut input, keysyms::XKB_KEY_a, &CTRL, Some("\u{1}"));
+        press_with(&mut input, keysyms::XKB_KEY_Tab, &NO_MODIFIERS, Some("\t"));
+        assert!(input.buffer.is_empty());
+        assert_eq!(input.screen_state, ScreenState::Cleared);
+        // Combinations that come with text are, since that's what the
+        // layout types with them.
+        press_with(&mut input, keysyms::XKB_KEY_c, &alt, Some("c"));
+        type_text(&mut input, "orrect horse");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    /// Types and deletes in a password made of multi-code point characters.
+    fn type_unicode(password: &str, typed: &[&str], backspaces: usize) {
+        let mock = MockAuthenticator::new().with_user("alice", &[password]);
+        let (mut input, _wakeup) = input(mock);
+        // Every string is what a single key press sent.
+        for text in typed {
+            press_with(&mut input, 0, &NO_MODIFIERS, Some(text));
+        }
+        for _ in 0..backspaces {
+            press(&mut input, keysyms::XKB_KEY_BackSpace);
+        }
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in(), "{:?} was typed in as {:?}",
+                password, typed);
+    }
+
+    #[test]
+    fn editing_keys_repeat() {
+        let mock = MockAuthenticator::new().with_user("alice", &["1234"]);
+        let (mut input, _wakeup) = input(mock);
+        input.set_repeat_info(100, 20);
+        type_text(&mut input, "abcdefgh");
+        input.handle_key(keysyms::XKB_KEY_BackSpace, &NO_MODIFIERS, KeyState::Pressed, None);
+        assert_eq!(input.buffer.as_str(), "abcdefg");
+        assert!(input.timeout().unwrap() <= Duration::from_millis(20));
+        input.poll();
+        assert_eq!(input.buffer.as_str(), "abcdefg");
+        thread::sleep(Duration::from_millis(45));
+        input.poll();
+        assert!(input.buffer.len() <= 5, "BackSpace did not repeat");
+        input.handle_key(keysyms::XKB_KEY_BackSpace, &NO_MODIFIERS, KeyState::Released, None);
+        assert_eq!(input.timeout(), None);
+        let typed = input.buffer.as_str().to_string();
+        thread::sleep(Duration::from_millis(30));
+        input.poll();
+        assert_eq!(input.buffer.as_str(), typed);
+        // Characters are only typed once, however long they're held down.
+        input.handle_key('x' as u32, &NO_MODIFIERS, KeyState::Pressed, Some("x".into()));
+        assert_eq!(input.timeout(), None);
+        thread::sleep(Duration::from_millis(30));
+        input.poll();
+        assert_eq!(input.buffer.as_str(), format!("{}x", typed));
+        input.set_repeat_info(i32::MAX, 0);
+        assert_eq!(input.repeat_interval, Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn accented_input() {
+        // Precomposed, and with a combining accent sent separately.
+        type_unicode("caf\u{e9}", &["c", "a", "f", "\u{e9}"], 0);
+        type_unicode("cafe\u{301}", &["c", "a", "f", "e", "\u{301}"], 0);
+        // Backspace deletes the whole accented letter, not just the accent.
+        type_unicode("caf", &["c", "a", "f", "e", "\u{301}"], 1);
+        type_unicode("na\u{ef}v", &["n", "a", "\u{ef}", "v", "e\u{301}"], 1);
+    }
+
+    #[test]
+    fn cjk_input() {
+        type_unicode("\u{5bc6}\u{7801}", &["\u{5bc6}\u{7801}"], 0);
+        type_unicode("\u{5bc6}", &["\u{5bc6}", "\u{7801}"], 1);
+        // Hangul syllable made of conjoining jamo.
+        type_unicode("\u{d55c}", &["\u{d55c}", "\u{1100}", "\u{1161}", "\u{11ab}"], 1);
+    }
+
+    #[test]
+    fn emoji_input() {
+        // Skin tone modifier, flag and a ZWJ sequence are one character each.
+        type_unicode("\u{1f44d}\u{1f3fd}", &["\u{1f44d}", "\u{1f3fd}"], 0);
+        type_unicode("a", &["a", "\u{1f44d}", "\u{1f3fd}"], 1);
+        type_unicode("a", &["a", "\u{1f1f3}\u{1f1f1}"], 1);
+        type_unicode("a", &["a", "\u{1f469}\u{200d}\u{1f469}\u{200d}\u{1f467}"], 1);
+        type_unicode("\u{1f511}", &["\u{1f511}", "\u{1f469}\u{200d}\u{1f467}"], 1);
+    }
+
+    #[test]
+    fn dead_keys_are_composed() {
+        let table = "<dead_acute> <e> : \"\u{e9}\" eacute\n";
+        let mock = MockAuthenticator::new().with_user("alice", &["caf\u{e9}"]);
+        let (input, _wakeup) = input(mock);
+        let mut input = input.with_compose(Compose::from_table(table).unwrap());
+        type_text(&mut input, "caf");
+        press_with(&mut input, keysyms::XKB_KEY_dead_acute, &NO_MODIFIERS, None);
+        press_with(&mut input, keysyms::XKB_KEY_e, &NO_MODIFIERS, Some("e"));
+        assert_eq!(input.buffer.as_str(), "caf\u{e9}");
+        // A sequence that doesn't exist is dropped entirely.
+        press_with(&mut input, keysyms::XKB_KEY_dead_acute, &NO_MODIFIERS, None);
+        press_with(&mut input, keysyms::XKB_KEY_x, &NO_MODIFIERS, Some("x"));
+        press(&mut input, keysyms::XKB_KEY_Return);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn lock_keys_are_shown() {
+        let mock = MockAuthenticator::new().with_user("alice", &["1234"]);
+        let (mut input, _wakeup) = input(mock);
+        assert!(input.overlay().is_empty());
+        input.set_locks(true, false);
+        assert_eq!(input.overlay(), vec!["Warning: Caps Lock is on".to_string()]);
+        assert!(input.new_color.take().is_some(), "Overlay was not redrawn");
+        input.set_locks(false, true);
+        assert_eq!(input.overlay(), vec!["Num Lock is on".to_string()]);
+    }
+
+    #[test]
+    fn layout_is_shown_and_switched() {
+        let mock = MockAuthenticator::new().with_user("alice", &["1234"]);
+        let (input, _wakeup) = input(mock);
+        let mut input = input.with_layout_switch("super+space".parse().unwrap());
+        input.set_layouts(vec!["English (US)".into(), "German".into()]);
+        assert_eq!(input.overlay(), vec!["Layout: English (US)".to_string()]);
+        type_text(&mut input, "12");
+        let logo = ModifiersState { logo: true, .. NO_MODIFIERS };
+        press_with(&mut input, keysyms::XKB_KEY_space, &logo, Some(" "));
+        assert_eq!(input.buffer.as_str(), "12");
+        assert_eq!(input.forced_layout(), Some(1));
+        assert_eq!(input.overlay(), vec!["Layout: German".to_string()]);
+        press_with(&mut input, keysyms::XKB_KEY_space, &logo, Some(" "));
+        assert_eq!(input.forced_layout(), Some(0));
+        input.set_layouts(vec!["German".into()]);
+        assert_eq!(input.forced_layout(), Some(0));
+    }
+
+    #[test]
+    fn controls_act_like_keys() {
+        let mock = MockAuthenticator::new().with_user("alice", &["1234"]);
+        let (mut input, _wakeup) = input(mock);
+        assert_eq!(input.controls(), vec![vec![Control::new("Unlock", Action::Submit),
+                                               Control::new("Clear", Action::Clear)]]);
+        type_text(&mut input, "12");
+        input.activate(Action::Clear);
+        assert_eq!(input.buffer.as_str(), "");
+        input.set_layouts(vec!["English (US)".into(), "German".into()]);
+        assert!(input.controls()[0].contains(&Control::new("Layout", Action::SwitchLayout)));
+        input.activate(Action::SwitchLayout);
+        assert_eq!(input.forced_layout(), Some(1));
+        type_text(&mut input, "1234");
+        input.activate(Action::Submit);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn keypad_types_password() {
+        let mock = MockAuthenticator::new().with_user("alice", &["Ab1"]);
+        let (input, _wakeup) = input(mock);
+        let mut input = input.with_keypad(Keypad::Qwerty);
+        assert_eq!(input.controls().len(), 6);
+        input.activate(Action::Type('x'));
+        input.activate(Action::Delete);
+        input.activate(Action::Shift);
+        assert!(input.controls()[2].contains(&Control::new("A", Action::Type('A'))));
+        input.activate(Action::Type('A'));
+        input.activate(Action::Shift);
+        input.activate(Action::Type('b'));
+        input.activate(Action::Type('1'));
+        assert_eq!(input.buffer.as_str(), "Ab1");
+        input.activate(Action::Submit);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn forced_num_lock_types_digits() {
+        let mock = MockAuthenticator::new().with_user("alice", &["1234"]);
+        let (input, _wakeup) = input(mock);
+        let mut input = input.with_forced_num_lock();
+        assert_eq!(input.overlay(), vec!["Keypad types digits".to_string()]);
+        input.set_locks(false, true);
+        assert_eq!(input.overlay(), vec!["Num Lock is on".to_string()]);
+        input.set_locks(false, false);
+        for &keysym in &[keysyms::XKB_KEY_KP_End, keysyms::XKB_KEY_KP_Down,
+                         keysyms::XKB_KEY_KP_Next, keysyms::XKB_KEY_KP_Left] {
+            press(&mut input, keysym);
+        }
+        assert_eq!(input.buffer.as_str(), "1234");
+        press(&mut input, keysyms::XKB_KEY_KP_Enter);
+        wait(&mut input);
+        assert!(input.is_logged_in());
+    }
+
+    #[test]
+    fn lockout_blocks_attempts() {
+        let mock = MockAuthenticator::new().with_user("alice", &["hunter2"]);
+        let policy = Policy {
+            lockout_after: 2,
+            lockout_duration: Duration::from_secs(3600),
+            .. NO_DELAY
+        };
+        let (mut input, _wakeup) = input_with_policy(mock, policy);
+        for _ in 0..2 {
+            type_text(&mut input, "hunter3");
+            press(&mut input, keysyms::XKB_KEY_Return);
+            wait(&mut input);
         }
+        assert!(input.throttle.is_locked_out());
+        type_text(&mut input, "hunter2");
+        press(&mut input, keysyms::XKB_KEY_Return);
+        assert!(!input.is_verifying());
+        assert!(!input.is_logged_in());
+        assert_eq!(input.throttle.failures(), 2);
+        assert_eq!(input.buffer.as_str(), "hunter2");
+        assert!(input.overlay().iter().any(|line| line.contains("locked for 60:00")));
     }
 }
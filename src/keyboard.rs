@@ -0,0 +1,212 @@
+//! Module containing the keyboard handler, which sits between the compositor
+//! and `Input`.
+//!
+//! `MappedKeyboard` turns key codes into keysyms and text, but keeps the
+//! modifiers to itself until a key is pressed. This forwards everything to
+//! it, and lets `Input` know about Caps Lock and Num Lock as soon as they
+//! change, so the lock screen can warn about them before anything is typed.
+//! How fast keys repeat is passed on too, since `MappedKeyboard` doesn't
+//! repeat keys itself.
+//!
+//! It also keeps track of the keyboard layout. When the user switches layouts
+//! on the lock screen, the compositor's layout is overridden in the modifiers
+//! that are forwarded, since only the compositor could change it for real.
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use libc;
+
+use wayland_client::EventQueueHandle;
+use wayland_client::protocol::{wl_keyboard, wl_surface};
+use wayland_kbd::{self, MappedKeyboard, ModifiersState};
+
+use input::Input;
+use layout;
+
+/// The modifier Caps Lock sets.
+const CAPS_LOCK: &'static str = "Lock";
+/// The modifier Num Lock is bound to in practically every keymap.
+const NUM_LOCK: &'static str = "Mod2";
+
+/// Lets `MappedKeyboard` type into the `Input` that every keyboard shares.
+struct SharedInput(Arc<Mutex<Input>>);
+
+impl wayland_kbd::Handler for SharedInput {
+    fn key(&mut self,
+           _: &mut EventQueueHandle,
+           _: &wl_keyboard::WlKeyboard,
+           _: u32,
+           _: u32,
+           modifiers: &ModifiersState,
+           _: u32,
+           keysym: u32,
+           state: wl_keyboard::KeyState,
+           text: Option<String>) {
+        self.0.lock().expect("Input was poisoned")
+            .handle_key(keysym, modifiers, state, text);
+    }
+}
+
+pub struct Keyboard {
+    mapped: MappedKeyboard<SharedInput>,
+    input: Arc<Mutex<Input>>,
+    /// Depressed, latched and locked modifiers, as last sent by the
+    /// compositor.
+    mods: (u32, u32, u32),
+    /// The layout the compositor has active.
+    group: u32,
+    /// The layout the user switched to on the lock screen, as last passed
+    /// on to `MappedKeyboard`.
+    forced_group: Option<u32>,
+    /// The bits of Caps Lock and Num Lock in the modifiers, as looked up in
+    /// the keymap. Zero until there is a keymap, or if it has no such
+    /// modifier.
+    caps_lock_mask: u32,
+    num_lock_mask: u32
+}
+
+impl Keyboard {
+    pub fn new(input: Arc<Mutex<Input>>) -> Option<Self> {
+        MappedKeyboard::new(SharedInput(input.clone())).ok()
+            .map(|mapped| Keyboard {
+                mapped,
+                input,
+                mods: (0, 0, 0),
+                group: 0,
+                forced_group: None,
+                caps_lock_mask: 0,
+                num_lock_mask: 0
+            })
+    }
+
+    fn input(&self) -> MutexGuard<Input> {
+        self.input.lock().expect("Input was poisoned")
+    }
+
+    /// Passes the last modifiers on to `MappedKeyboard`, with the layout
+    /// the user picked if they switched layouts.
+    fn forward_modifiers(&mut self,
+                         evqh: &mut EventQueueHandle,
+                         proxy: &wl_keyboard::WlKeyboard,
+                         serial: u32) {
+        let (depressed, latched, locked) = self.mods;
+        self.forced_group = self.input().forced_layout();
+        let group = self.forced_group.unwrap_or(self.group);
+        wl_keyboard::Handler::modifiers(&mut self.mapped, evqh, proxy, serial,
+                                        depressed, latched, locked, group);
+        self.input().set_layout(group);
+    }
+
+    /// Starts using the layout the user switched to, if they switched
+    /// since the last time.
+    fn sync_layout(&mut self,
+                   evqh: &mut EventQueueHandle,
+                   proxy: &wl_keyboard::WlKeyboard,
+                   serial: u32) {
+        let forced_group = self.input().forced_layout();
+        if forced_group != self.forced_group {
+            self.forward_modifiers(evqh, proxy, serial);
+        }
+    }
+}
+
+/// Reads the keymap the compositor sent, without closing the file or moving
+/// its offset, since `MappedKeyboard` still needs it.
+fn read_keymap(fd: RawFd, size: u32) -> Option<String> {
+    let fd = unsafe { libc::dup(fd) };
+    if fd < 0 {
+        return None
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    let mut keymap = vec![0; size as usize];
+    file.read_exact_at(&mut keymap, 0).ok()?;
+    String::from_utf8(keymap).ok()
+}
+
+impl wl_keyboard::Handler for Keyboard {
+    fn keymap(&mut self,
+              evqh: &mut EventQueueHandle,
+              proxy: &wl_keyboard::WlKeyboard,
+              format: wl_keyboard::KeymapFormat,
+              fd: RawFd,
+              size: u32) {
+        // `MappedKeyboard` only takes the file, so it compiles its own copy.
+        let keymap = match format {
+            wl_keyboard::KeymapFormat::XkbV1 => {
+                read_keymap(fd, size).and_then(|keymap| layout::Keymap::new(&keymap))
+            },
+            _ => None
+        };
+        let names = keymap.as_ref()
+            .map(layout::Keymap::names)
+            .unwrap_or_default();
+        let mask = |name| keymap.as_ref()
+            .and_then(|keymap| keymap.modifier_mask(name))
+            .unwrap_or(0);
+        self.caps_lock_mask = mask(CAPS_LOCK);
+        self.num_lock_mask = mask(NUM_LOCK);
+        self.input().set_layouts(names);
+        wl_keyboard::Handler::keymap(&mut self.mapped, evqh, proxy, format, fd, size)
+    }
+
+    fn enter(&mut self,
+             evqh: &mut EventQueueHandle,
+             proxy: &wl_keyboard::WlKeyboard,
+             serial: u32,
+             surface: &wl_surface::WlSurface,
+             keys: Vec<u8>) {
+        wl_keyboard::Handler::enter(&mut self.mapped, evqh, proxy, serial, surface, keys)
+    }
+
+    fn leave(&mut self,
+             evqh: &mut EventQueueHandle,
+             proxy: &wl_keyboard::WlKeyboard,
+             serial: u32,
+             surface: &wl_surface::WlSurface) {
+        self.input().stop_repeat();
+        wl_keyboard::Handler::leave(&mut self.mapped, evqh, proxy, serial, surface)
+    }
+
+    fn key(&mut self,
+           evqh: &mut EventQueueHandle,
+           proxy: &wl_keyboard::WlKeyboard,
+           serial: u32,
+           time: u32,
+           key: u32,
+           state: wl_keyboard::KeyState) {
+        // Either could have switched layouts, the key itself or an
+        // on-screen control since the last key.
+        self.sync_layout(evqh, proxy, serial);
+        wl_keyboard::Handler::key(&mut self.mapped, evqh, proxy, serial, time, key, state);
+        self.sync_layout(evqh, proxy, serial);
+    }
+
+    fn modifiers(&mut self,
+                 evqh: &mut EventQueueHandle,
+                 proxy: &wl_keyboard::WlKeyboard,
+                 serial: u32,
+                 mods_depressed: u32,
+                 mods_latched: u32,
+                 mods_locked: u32,
+                 group: u32) {
+        self.mods = (mods_depressed, mods_latched, mods_locked);
+        self.group = group;
+        self.forward_modifiers(evqh, proxy, serial);
+        self.input().set_locks(mods_locked & self.caps_lock_mask != 0,
+                               mods_locked & self.num_lock_mask != 0);
+    }
+
+    fn repeat_info(&mut self,
+                   evqh: &mut EventQueueHandle,
+                   proxy: &wl_keyboard::WlKeyboard,
+                   rate: i32,
+                   delay: i32) {
+        self.input().set_repeat_info(rate, delay);
+        wl_keyboard::Handler::repeat_info(&mut self.mapped, evqh, proxy, rate, delay)
+    }
+}
+
+declare_handler!(Keyboard, wl_keyboard::Handler, wl_keyboard::WlKeyboard);
@@ -0,0 +1,220 @@
+//! Keyboard layouts, and the key combination that switches between them.
+//!
+//! A password typed in the wrong layout fails for no visible reason, so the
+//! lock screen shows which layout is active. The compositor can't be asked to
+//! switch layouts, but the keysyms are looked up on our side, so the layout
+//! used for them can be picked here instead.
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::str::FromStr;
+
+use libc::c_char;
+use wayland_kbd::ModifiersState;
+
+use xkb::*;
+
+/// The keymap the compositor sent, compiled once so that everything can be
+/// looked up in it.
+pub struct Keymap {
+    keymap: *mut xkb_keymap
+}
+
+impl Keymap {
+    /// Compiles a keymap in the text format, which may end in a null byte.
+    pub fn new(keymap: &str) -> Option<Self> {
+        let keymap = CString::new(keymap.trim_end_matches('\0')).ok()?;
+        unsafe {
+            let context = xkb_context_new(XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None
+            }
+            let compiled = xkb_keymap_new_from_string(context, keymap.as_ptr(),
+                                                      XKB_KEYMAP_FORMAT_TEXT_V1,
+                                                      XKB_KEYMAP_COMPILE_NO_FLAGS);
+            // The keymap keeps its own reference to the context.
+            xkb_context_unref(context);
+            if compiled.is_null() {
+                return None
+            }
+            Some(Keymap { keymap: compiled })
+        }
+    }
+
+    /// The names of the layouts, in the order of their groups.
+    pub fn names(&self) -> Vec<String> {
+        unsafe {
+            (0..xkb_keymap_num_layouts(self.keymap))
+                .map(|index| {
+                    let name = xkb_keymap_layout_get_name(self.keymap, index);
+                    if name.is_null() {
+                        format!("Layout {}", index + 1)
+                    } else {
+                        CStr::from_ptr(name).to_string_lossy().into_owned()
+                    }
+                })
+                .collect()
+        }
+    }
+
+    /// The bit of the modifier called `name` in the masks the compositor
+    /// sends, like "Lock" for Caps Lock or "Mod2" for Num Lock.
+    pub fn modifier_mask(&self, name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        let index = unsafe { xkb_keymap_mod_get_index(self.keymap, name.as_ptr()) };
+        if index == XKB_MOD_INVALID || index >= 32 {
+            return None
+        }
+        Some(1 << index)
+    }
+}
+
+impl Drop for Keymap {
+    fn drop(&mut self) {
+        unsafe {
+            xkb_keymap_unref(self.keymap);
+        }
+    }
+}
+
+/// A key pressed while holding down modifiers, like `ctrl+alt+space`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Combo {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    logo: bool,
+    keysym: u32
+}
+
+impl Combo {
+    /// Whether pressing `keysym` with `modifiers` held down is this
+    /// combination.
+    pub fn matches(&self, keysym: u32, modifiers: &ModifiersState) -> bool {
+        keysym == self.keysym
+            && modifiers.ctrl == self.ctrl
+            && modifiers.alt == self.alt
+            && modifiers.shift == self.shift
+            && modifiers.logo == self.logo
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ComboError {
+    UnknownModifier(String),
+    UnknownKey(String)
+}
+
+impl fmt::Display for ComboError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ComboError::UnknownModifier(ref name) => {
+                write!(f, "Unknown modifier {:?}, expected ctrl, alt, shift or super",
+                       name)
+            },
+            ComboError::UnknownKey(ref name) => write!(f, "Unknown key {:?}", name)
+        }
+    }
+}
+
+impl FromStr for Combo {
+    type Err = ComboError;
+
+    /// Parses modifiers and a key name, separated by `+`. The key name is
+    /// that of its keysym, like `space` or `Shift_L`.
+    fn from_str(combo: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = combo.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or("");
+        let mut result = Combo {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+            keysym: keysym_from_name(key)
+                .ok_or_else(|| ComboError::UnknownKey(key.into()))?
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => result.ctrl = true,
+                "alt" => result.alt = true,
+                "shift" => result.shift = true,
+                "super" | "logo" => result.logo = true,
+                _ => return Err(ComboError::UnknownModifier(modifier.into()))
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn keysym_from_name(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let keysym = unsafe {
+        xkb_keysym_from_name(name.as_ptr() as *const c_char,
+                             XKB_KEYSYM_CASE_INSENSITIVE)
+    };
+    if keysym == XKB_KEY_NoSymbol {
+        None
+    } else {
+        Some(keysym)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wayland_kbd::ModifiersState;
+    use wayland_kbd::keysyms::{XKB_KEY_Shift_L, XKB_KEY_space};
+
+    use super::{Combo, ComboError, Keymap};
+
+    const KEYMAP: &'static str = "\
+xkb_keymap {
+    xkb_keycodes { <SPCE> = 65; };
+    xkb_types { include \"basic\" };
+    xkb_compat { include \"basic\" };
+    xkb_symbols {
+        name[Group1] = \"English (US)\";
+        name[Group2] = \"German\";
+        key <SPCE> { [ space ], [ space ] };
+    };
+};\0";
+
+    fn modifiers(ctrl: bool, alt: bool, shift: bool, logo: bool) -> ModifiersState {
+        ModifiersState {
+            ctrl,
+            alt,
+            shift,
+            caps_lock: false,
+            logo,
+            num_lock: false
+        }
+    }
+
+    #[test]
+    fn layout_names() {
+        let keymap = Keymap::new(KEYMAP).expect("Could not compile keymap");
+        assert_eq!(keymap.names(), vec!["English (US)", "German"]);
+        assert!(Keymap::new("not a keymap").is_none());
+    }
+
+    #[test]
+    fn modifier_masks() {
+        let keymap = Keymap::new(KEYMAP).expect("Could not compile keymap");
+        assert_eq!(keymap.modifier_mask("Lock"), Some(1 << 1));
+        assert_eq!(keymap.modifier_mask("Mod2"), Some(1 << 4));
+        assert_eq!(keymap.modifier_mask("NoSuchModifier"), None);
+    }
+
+    #[test]
+    fn combos() {
+        let combo: Combo = "super+space".parse().unwrap();
+        assert!(combo.matches(XKB_KEY_space, &modifiers(false, false, false, true)));
+        assert!(!combo.matches(XKB_KEY_space, &modifiers(false, false, false, false)));
+        assert!(!combo.matches(XKB_KEY_space, &modifiers(true, false, false, true)));
+        let combo: Combo = "Ctrl + alt+Shift_L".parse().unwrap();
+        assert!(combo.matches(XKB_KEY_Shift_L, &modifiers(true, true, false, false)));
+        assert_eq!("hyper+space".parse::<Combo>(),
+                   Err(ComboError::UnknownModifier("hyper".into())));
+        assert_eq!("ctrl+nokey".parse::<Combo>(),
+                   Err(ComboError::UnknownKey("nokey".into())));
+    }
+}
@@ -8,30 +8,56 @@ extern crate clap;
 extern crate dbus;
 extern crate image;
 extern crate rand;
+extern crate unicode_segmentation;
 #[macro_use] extern crate way_cooler_client_helpers;
 
 use way_cooler_client_helpers::color;
 
+mod auth;
+mod compose;
+mod controls;
+mod font;
+mod fprint;
+mod harden;
 mod input;
+mod keyboard;
+mod layout;
+mod output;
 mod window;
 mod pam;
+mod pointer;
 mod effects;
-use effects::Blur;
+mod seat;
+mod secret;
+mod throttle;
+mod touch;
+mod totp;
+mod wakeup;
+mod xkb;
+use compose::Compose;
+use controls::Keypad;
+use wakeup::Wakeup;
 
-use input::{Input};
+use input::{Feedback, Input};
+use output::Output;
+use seat::Seats;
+use pam::PamAuthenticator;
+use throttle::{Policy, Throttle};
 use window::{Resolution, Window};
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 
-use wayland_client::EnvHandler;
-use wayland_client::protocol::{wl_compositor, wl_shell, wl_shm,
-                               wl_seat, wl_keyboard, wl_output};
-use wayland_kbd::MappedKeyboard;
+use std::os::unix::io::AsRawFd;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use wayland_client::{EnvHandler, EventQueue, Proxy};
+use wayland_client::protocol::{wl_compositor, wl_shell, wl_shm, wl_display,
+                               wl_output};
 use wl_compositor::WlCompositor;
 
 wayland_env!(WaylandEnv,
              compositor: wl_compositor::WlCompositor,
-             seat: wl_seat::WlSeat,
              shell: wl_shell::WlShell,
              shm: wl_shm::WlShm,
              output: wl_output::WlOutput
@@ -74,8 +100,98 @@ fn main() {
              .value_name("fancy-blur")
              .takes_value(false)
              .help("Enable fancy blur option"))
+        .arg(Arg::with_name("feedback")
+             .long("feedback")
+             .value_name("MODE")
+             .possible_values(&["state", "legacy"])
+             .default_value("state")
+             .help("What the screen color shows: \"state\" only shows key \
+                    presses and whether the password is being checked or was \
+                    wrong, \"legacy\" is derived from what was typed, which \
+                    gives away information about the password"))
+        .arg(Arg::with_name("service")
+             .long("service")
+             .value_name("NAME")
+             .default_value("wc-lock")
+             .help("PAM service to authenticate with, i.e the file in /etc/pam.d"))
+        .arg(Arg::with_name("user")
+             .long("user")
+             .short("u")
+             .value_name("USER")
+             .multiple(true)
+             .number_of_values(1)
+             .help("User that is allowed to unlock, can be given multiple \
+                    times. Defaults to the user running wc-lock"))
+        .arg(Arg::with_name("fingerprint")
+             .long("fingerprint")
+             .takes_value(false)
+             .help("Also allow unlocking with a fingerprint, through fprintd"))
+        .arg(Arg::with_name("totp")
+             .long("totp")
+             .takes_value(false)
+             .help("Ask for a one-time code after the password, checked against \
+                    the secret in ~/.config/wc-lock/totp"))
+        .arg(Arg::with_name("totp-dir")
+             .long("totp-dir")
+             .value_name("DIR")
+             .requires("totp")
+             .help("Read the one-time code secrets from DIR/<user> instead"))
+        .arg(Arg::with_name("force-numlock")
+             .long("force-numlock")
+             .takes_value(false)
+             .help("Make the keypad type digits even when Num Lock is off. \
+                    Num Lock itself is left alone, the compositor's state \
+                    can't be changed"))
+        .arg(Arg::with_name("layout-switch")
+             .long("layout-switch")
+             .value_name("COMBO")
+             .default_value("super+space")
+             .help("Switch to the next keyboard layout with this key combination, \
+                    like ctrl+alt+space, or none to not allow switching"))
+        .arg(Arg::with_name("hide-cursor")
+             .long("hide-cursor")
+             .takes_value(false)
+             .help("Hide the cursor instead of showing an arrow to click \
+                    the on-screen buttons with"))
+        .arg(Arg::with_name("keypad")
+             .long("keypad")
+             .value_name("KEYPAD")
+             .possible_values(&["none", "pin", "qwerty"])
+             .default_value("none")
+             .help("Draw a keypad to type with by clicking or tapping: \
+                    \"pin\" only has digits, \"qwerty\" has letters too"))
+        .arg(Arg::with_name("backoff")
+             .long("backoff")
+             .value_name("SECONDS")
+             .default_value("1")
+             .help("How long to wait after the first failed attempt, \
+                    doubled after every failure after that"))
+        .arg(Arg::with_name("max-backoff")
+             .long("max-backoff")
+             .value_name("SECONDS")
+             .default_value("30")
+             .help("The longest wait between two attempts"))
+        .arg(Arg::with_name("lockout-after")
+             .long("lockout-after")
+             .value_name("FAILURES")
+             .default_value("0")
+             .help("Lock out for a while after this many failures in a row, \
+                    0 to never lock out"))
+        .arg(Arg::with_name("lockout")
+             .long("lockout")
+             .value_name("SECONDS")
+             .default_value("300")
+             .help("How long a lockout lasts"))
+        .arg(Arg::with_name("persist-failures")
+             .long("persist-failures")
+             .takes_value(false)
+             .help("Remember failed attempts across restarts of wc-lock"))
         .get_matches();
 
+    // Do this before anything sensitive is loaded into memory.
+    let protections = harden::harden();
+    println!("Active protections: {}", protections);
+
     let (display, mut event_queue) = match wayland_client::default_connect() {
         Ok(ret) => ret,
         Err(e) => panic!("Cannot connect to wayland server: {:?}", e)
@@ -97,54 +213,102 @@ fn main() {
             ::std::process::exit(1);
         }
     };
-    // Fetch the output now that it has been declared by the compositor.
-    use wl_output::WlOutput;
-    let outputs = get_all_wayland!(env_id, registry, &mut event_queue, WlOutput, "wl_output")
-        .expect("Could not get outputs");
-    let resolutions: Vec<usize> = outputs.iter()
-        .map(|output| {
-            let res = Resolution::new();
-            let resolution_id = event_queue.add_handler(res);
-            event_queue.register::<_, Resolution>(output, resolution_id);
-            resolution_id
-        }).collect();
-    let mut blurs = Vec::with_capacity(outputs.len());
-    let mut windows = Vec::with_capacity(outputs.len());
+    // Authentication happens on other threads, which use this to let
+    // the main loop know they have something for it.
+    let (mut wakeup, waker) = Wakeup::new()
+        .expect("Could not create wakeup pipe");
     // Set up `Input`, which processes user input before passing it off to PAM
     // for authentication.
-    let input = MappedKeyboard::new(Input::new()).ok()
-        .expect("Could not create input handler");
-    let input_id = event_queue.add_handler(input);
-    let keyboard = get_keyboard(env_id, &mut event_queue);
-    event_queue.register::<_, MappedKeyboard<Input>>(&keyboard, input_id);
-    event_queue.dispatch().expect("Could not dispatch resolution");
-    let mut output_count = 0;
-    for (output, resolution_id) in outputs.iter().zip(resolutions.clone()) {
-        // Set up `Resolution`, which ensures the lockscreen is the same
-        // size as the output, even if it resizes.
-        event_queue.register::<_, Resolution>(&output, resolution_id);
-        let surface = compositor.create_surface();
-
-        desktop_shell.set_lock_surface(&output, &surface);
-        event_queue.dispatch_pending().unwrap();
-        // Set up `Window`, which takes care of drawing to the buffer.
-        // It uses the `Resolution` to determine how big to make the buffer.
-        let window = Window::new(resolution_id, surface, output, env_id, event_queue.state());
-        let shell_surface = window.shell_surface();
-        let window_id = event_queue.add_handler(window);
-        event_queue.register::<_, Window>(&shell_surface, window_id);
-        output_count += 1;
-        windows.push(window_id);
-
-        if matches.is_present("fancy-blur") {
-            let blur = Blur::new(resolution_id, window_id, output_count, event_queue.state());
-            blurs.push(blur);
+    let service = matches.value_of("service").unwrap();
+    let authenticator = Arc::new(PamAuthenticator::new(service));
+    let users = match matches.values_of("user") {
+        Some(users) => users.map(String::from).collect(),
+        None => vec![auth::current_username()]
+    };
+    let state_file = if matches.is_present("persist-failures") {
+        throttle::default_state_file()
+    } else {
+        None
+    };
+    let throttle = Throttle::new(throttle_policy(&matches), state_file);
+    let mut input = Input::new(users, authenticator,
+                               throttle, waker);
+    if !input.is_buffer_locked() {
+        eprintln!("Warning: Could not lock password buffers in memory");
+    }
+    match Compose::from_locale() {
+        Some(compose) => input = input.with_compose(compose),
+        None => eprintln!("Warning: Could not load the compose table, \
+                           dead keys and the Compose key won't work")
+    }
+    if matches.value_of("feedback") == Some("legacy") {
+        input = input.with_feedback(Feedback::Legacy);
+    }
+    if matches.is_present("fingerprint") {
+        input = input.with_fingerprint(fprint::Config::default());
+    }
+    if matches.is_present("totp") {
+        let secrets = match matches.value_of("totp-dir") {
+            Some(dir) => totp::Secrets::Dir(dir.into()),
+            None => totp::Secrets::Home
+        };
+        let used_codes = totp::UsedCodes::new(totp::default_used_codes_file());
+        input = input.with_totp(secrets, used_codes);
+    }
+    if matches.is_present("force-numlock") {
+        input = input.with_forced_num_lock();
+    }
+    match matches.value_of("layout-switch").unwrap() {
+        "none" => {},
+        combo => match combo.parse() {
+            Ok(combo) => input = input.with_layout_switch(combo),
+            Err(e) => {
+                eprintln!("--layout-switch: {}", e);
+                ::std::process::exit(1);
+            }
         }
     }
+    match matches.value_of("keypad") {
+        Some("pin") => input = input.with_keypad(Keypad::Pin),
+        Some("qwerty") => input = input.with_keypad(Keypad::Qwerty),
+        _ => {}
+    }
+    // Shared by the keyboards of every seat.
+    let input = Arc::new(Mutex::new(input));
+    // Clicks are matched to the controls on screen in the main loop.
+    let (click_sender, clicks) = mpsc::channel();
+    // Devices are registered once the seats say they have them.
+    let mut seats = {
+        let state = event_queue.state();
+        let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
+        Seats::new(input.clone(),
+                   compositor.clone().expect("Compositor was not initialized"),
+                   env.shm.clone().expect("Shm was not initialized"),
+                   matches.is_present("hide-cursor"),
+                   click_sender)
+    };
+    seats.update(&registry, env_id, &mut event_queue);
+    // Lock every output there is now, later ones are locked as they come.
+    let mut outputs: Vec<Output> = Vec::new();
+    for (name, version) in output::advertised(&mut event_queue, env_id) {
+        let blur = if matches.is_present("fancy-blur") {
+            Some(outputs.len() as u32 + 1)
+        } else {
+            None
+        };
+        let output = Output::lock(name, version, &registry, &compositor,
+                                  &desktop_shell, env_id, blur, &mut event_queue);
+        outputs.push(output);
+    }
+    let scale = output::cursor_scale(&outputs, &mut event_queue);
+    seats.set_cursor_scale(scale, &mut event_queue);
 
     // TODO parametrize
     let mut blur_times = 0;
     let blur_amount = 10.5;
+    // The windows start out black, and are redrawn with this when they're
+    // resized.
+    let mut last_color: color::Color = 0x000000.into();
     event_queue.dispatch()
         .expect("Could not dispatch queue");
     'main: loop {
@@ -153,37 +317,83 @@ fn main() {
         event_queue.dispatch_pending()
             .expect("Could not dispatch queue");
         if blur_times >= 0 && matches.is_present("fancy-blur") {
-            for (blur, resolution_id) in blurs.iter_mut().zip(resolutions.clone()) {
-                blur.blur(blur_amount, resolution_id, event_queue.state());
-                blur_times -= 1;
+            for output in &mut outputs {
+                if let Some(ref mut blur) = output.blur {
+                    blur.blur(blur_amount, output.resolution_id, event_queue.state());
+                    blur_times -= 1;
+                }
             }
             continue;
         }
-        event_queue.dispatch()
-            .expect("Could not dispatch queue");
+        // Wake up in time to update the countdown or repeat a held down key.
+        let timeout = input.lock().expect("Input was poisoned").timeout();
+        wait_for_events(&display, &mut event_queue, &mut wakeup, timeout);
+        // Tear down the lock surfaces of monitors that were removed, and
+        // lock the ones that were connected right away.
+        let advertised = output::advertised(&mut event_queue, env_id);
+        let (kept, removed): (Vec<Output>, Vec<Output>) = outputs.into_iter()
+            .partition(|output| advertised.iter().any(|&(name, _)| name == output.name));
+        outputs = kept;
+        for output in removed {
+            output.remove(&mut event_queue);
+        }
+        let mut connected = Vec::new();
+        for (name, version) in advertised {
+            if outputs.iter().all(|output| output.name != name) {
+                // Its screen can't be told apart from the others in
+                // way-cooler yet, so it's never blurred.
+                let output = Output::lock(name, version, &registry, &compositor,
+                                          &desktop_shell, env_id, None, &mut event_queue);
+                outputs.push(output);
+                connected.push(name);
+            }
+        }
+        // Seats can be added while locked too, like a docking station's.
+        seats.update(&registry, env_id, &mut event_queue);
+        let scale = output::cursor_scale(&outputs, &mut event_queue);
+        seats.set_cursor_scale(scale, &mut event_queue);
         let mut state = event_queue.state();
-        let zipped = resolutions.clone().into_iter()
-            .zip(windows.clone());
-        let color = {
-            let input = state.get_mut_handler::<MappedKeyboard<Input>>(input_id);
-            let handler = input.handler();
+        let (color, overlay, controls) = {
+            let mut handler = input.lock().expect("Input was poisoned");
+            for click in clicks.try_iter() {
+                let action = outputs.iter()
+                    .map(|output| {
+                        (*state.get_handler::<Resolution>(output.resolution_id),
+                         state.get_handler::<Window>(output.window_id))
+                    })
+                    .find(|&(_, window)| window.surface.equals(&click.surface))
+                    .and_then(|(res, _)| {
+                        controls::tap(res, &handler.controls(), click.down, (click.x, click.y))
+                    });
+                if let Some(action) = action {
+                    handler.activate(action);
+                }
+            }
+            handler.poll();
             if handler.is_logged_in() {
                 desktop_shell.unlock();
                 break 'main;
             }
-            handler.new_color.take()
+            (handler.new_color.take(), handler.overlay(), handler.controls())
         };
-        if matches.is_present("fancy-blur") {
-            for (blur, resolution_id) in blurs.iter_mut().zip(resolutions.clone()) {
-                let res: Resolution = *state.get_handler(resolution_id);
-                blur.random_input_circles(res, &mut state);
+        if let Some(color) = color {
+            last_color = color;
+        }
+        for output in &mut outputs {
+            let res: Resolution = *state.get_handler(output.resolution_id);
+            {
+                let window = state.get_mut_handler::<Window>(output.window_id);
+                window.set_overlay(overlay.clone());
+                window.set_controls(controls.clone());
             }
-        } else {
-            for (resolution_id, window_id) in zipped {
-                if let Some(color) = color {
-                    let res: Resolution = *state.get_handler(resolution_id);
-                    let window = state.get_mut_handler::<Window>(window_id);
-                    window.update_color(color, res);
+            match output.blur {
+                Some(ref mut blur) => blur.random_input_circles(res, &mut state),
+                None => {
+                    let window = state.get_mut_handler::<Window>(output.window_id);
+                    let redraw = color.is_some() || connected.contains(&output.name);
+                    if window.resize(res) || redraw {
+                        window.update_color(last_color, res);
+                    }
                 }
             }
         }
@@ -192,9 +402,69 @@ fn main() {
         .expect("Could not dispatch queue");
 }
 
-fn get_keyboard(env_id: usize, event_queue: &mut wayland_client::EventQueue)
-                -> wl_keyboard::WlKeyboard {
-    let state = event_queue.state();
-    let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
-    env.seat.get_keyboard().expect("Seat was destroyed")
+/// Builds the policy for failed attempts from the command line.
+fn throttle_policy(matches: &ArgMatches) -> Policy {
+    let number = |name| -> u64 {
+        matches.value_of(name).unwrap().parse()
+            .unwrap_or_else(|_| {
+                eprintln!("--{} has to be a whole number", name);
+                ::std::process::exit(1);
+            })
+    };
+    let lockout_after = number("lockout-after");
+    if lockout_after > u64::from(u32::MAX) {
+        eprintln!("--lockout-after can be at most {}", u32::MAX);
+        ::std::process::exit(1);
+    }
+    Policy {
+        base_delay: Duration::from_secs(number("backoff")),
+        max_delay: Duration::from_secs(number("max-backoff")),
+        lockout_after: lockout_after as u32,
+        lockout_duration: Duration::from_secs(number("lockout"))
+    }
+}
+
+/// Blocks until either the compositor sent something, another thread
+/// used the `Waker` or the timeout ran out, then dispatches whatever events
+/// came in.
+fn wait_for_events(display: &wl_display::WlDisplay,
+                   event_queue: &mut EventQueue,
+                   wakeup: &mut Wakeup,
+                   timeout: Option<Duration>) {
+    let guard = match event_queue.prepare_read() {
+        Some(guard) => guard,
+        None => {
+            // There are already events waiting to be dispatched.
+            event_queue.dispatch_pending()
+                .expect("Could not dispatch queue");
+            return
+        }
+    };
+    display.flush()
+        .expect("Could not flush display");
+    let mut fds = [
+        libc::pollfd { fd: display.get_fd(), events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: wakeup.as_raw_fd(), events: libc::POLLIN, revents: 0 }
+    ];
+    let timeout = match timeout {
+        // Rounded up, waking up early would just mean waiting again.
+        Some(timeout) => (timeout.as_secs() * 1000 +
+                          (timeout.subsec_nanos() as u64 + 999_999) / 1_000_000)
+            as libc::c_int,
+        None => -1
+    };
+    let ready = unsafe {
+        libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout)
+    };
+    if ready > 0 && fds[0].revents & libc::POLLIN != 0 {
+        guard.read_events()
+            .expect("Could not read events");
+    } else {
+        guard.cancel();
+    }
+    if fds[1].revents & libc::POLLIN != 0 {
+        wakeup.drain();
+    }
+    event_queue.dispatch_pending()
+        .expect("Could not dispatch queue");
 }
@@ -0,0 +1,110 @@
+//! Module containing the lock surfaces of the outputs, which keeps track of
+//! monitors that are connected or removed while the screen is locked.
+//!
+//! `EnvHandler` keeps the list of globals up to date as the compositor adds
+//! and removes them, so comparing the outputs in it with the ones that are
+//! locked tells which monitors came and went.
+
+use std::cmp;
+
+use wayland_client::{EnvHandler, EventQueue, Proxy};
+use wayland_client::protocol::{wl_compositor, wl_output, wl_registry};
+
+use ::WaylandEnv;
+use ::effects::Blur;
+use ::generated::client::desktop_shell::DesktopShell;
+use ::window::{Resolution, Window};
+
+/// An output that has a lock surface on it.
+pub struct Output {
+    /// The name of the output's global, which is how the compositor says
+    /// the output was removed.
+    pub name: u32,
+    /// Keeps the output bound, so its `Resolution` stays up to date.
+    _output: wl_output::WlOutput,
+    pub resolution_id: usize,
+    pub window_id: usize,
+    /// The blurred screen shown on the output, with `--fancy-blur`.
+    pub blur: Option<Blur>
+}
+
+impl Output {
+    /// Binds the output and puts a lock surface on it.
+    ///
+    /// `blur` is the output's number in way-cooler, to show its blurred
+    /// screen instead of a plain color.
+    pub fn lock(name: u32,
+                version: u32,
+                registry: &wl_registry::WlRegistry,
+                compositor: &wl_compositor::WlCompositor,
+                desktop_shell: &DesktopShell,
+                env_id: usize,
+                blur: Option<u32>,
+                event_queue: &mut EventQueue) -> Self {
+        // Outputs only report their scale from version 2, and it's only
+        // of use if surfaces can be given scaled buffers.
+        let version = if compositor.version() >= 3 {
+            cmp::min(version, 2)
+        } else {
+            1
+        };
+        let output = registry.bind::<wl_output::WlOutput>(version, name);
+        // Set up `Resolution`, which ensures the lockscreen is the same
+        // size as the output, even if it resizes.
+        let resolution_id = event_queue.add_handler(Resolution::new());
+        event_queue.register::<_, Resolution>(&output, resolution_id);
+        event_queue.sync_roundtrip().expect("Could not get resolution");
+        let surface = compositor.create_surface();
+
+        desktop_shell.set_lock_surface(&output, &surface);
+        event_queue.dispatch_pending().unwrap();
+        // Set up `Window`, which takes care of drawing to the buffer.
+        // It uses the `Resolution` to determine how big to make the buffer.
+        let window = Window::new(resolution_id, surface, &output, env_id, event_queue.state());
+        let shell_surface = window.shell_surface();
+        let window_id = event_queue.add_handler(window);
+        event_queue.register::<_, Window>(&shell_surface, window_id);
+        let blur = blur.map(|number| {
+            Blur::new(resolution_id, window_id, number, event_queue.state())
+        });
+        Output {
+            name,
+            _output: output,
+            resolution_id,
+            window_id,
+            blur
+        }
+    }
+
+    /// Tears down the lock surface, once the output was removed.
+    ///
+    /// The handlers stay in the event queue, since they can't be removed,
+    /// but nothing is sent to them anymore.
+    pub fn remove(self, event_queue: &mut EventQueue) {
+        let mut state = event_queue.state();
+        state.get_mut_handler::<Window>(self.window_id).destroy();
+    }
+}
+
+/// The names and versions of the outputs the compositor currently
+/// advertises.
+pub fn advertised(event_queue: &mut EventQueue, env_id: usize) -> Vec<(u32, u32)> {
+    let state = event_queue.state();
+    let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
+    env.globals().iter()
+        .filter(|&&(_, ref interface, _)| interface == "wl_output")
+        .map(|&(name, _, version)| (name, version))
+        .collect()
+}
+
+/// The largest scale of the locked outputs.
+///
+/// Cursors are drawn at it, so they're sharp on every output, and the
+/// compositor scales them down on the others.
+pub fn cursor_scale(outputs: &[Output], event_queue: &mut EventQueue) -> u32 {
+    let state = event_queue.state();
+    outputs.iter()
+        .map(|output| state.get_handler::<Resolution>(output.resolution_id).buffer_scale())
+        .max()
+        .unwrap_or(1)
+}
@@ -0,0 +1,97 @@
+//! Raw bindings to libpam, as declared in `security/pam_appl.h`.
+//!
+//! Only what `wc-lock` actually uses is declared here.
+
+#![allow(non_camel_case_types, dead_code)]
+
+use libc::{c_char, c_int, c_void};
+
+/// Opaque handle to a PAM transaction.
+pub enum pam_handle_t {}
+
+#[repr(C)]
+pub struct pam_message {
+    pub msg_style: c_int,
+    pub msg: *const c_char
+}
+
+#[repr(C)]
+pub struct pam_response {
+    pub resp: *mut c_char,
+    /// Unused, should always be 0.
+    pub resp_retcode: c_int
+}
+
+pub type pam_conv_fn = extern "C" fn(num_msg: c_int,
+                                     msg: *mut *const pam_message,
+                                     resp: *mut *mut pam_response,
+                                     appdata_ptr: *mut c_void) -> c_int;
+
+#[repr(C)]
+pub struct pam_conv {
+    pub conv: pam_conv_fn,
+    pub appdata_ptr: *mut c_void
+}
+
+// Return codes
+pub const PAM_SUCCESS: c_int = 0;
+pub const PAM_OPEN_ERR: c_int = 1;
+pub const PAM_SYMBOL_ERR: c_int = 2;
+pub const PAM_SERVICE_ERR: c_int = 3;
+pub const PAM_SYSTEM_ERR: c_int = 4;
+pub const PAM_BUF_ERR: c_int = 5;
+pub const PAM_PERM_DENIED: c_int = 6;
+pub const PAM_AUTH_ERR: c_int = 7;
+pub const PAM_CRED_INSUFFICIENT: c_int = 8;
+pub const PAM_AUTHINFO_UNAVAIL: c_int = 9;
+pub const PAM_USER_UNKNOWN: c_int = 10;
+pub const PAM_MAXTRIES: c_int = 11;
+pub const PAM_NEW_AUTHTOK_REQD: c_int = 12;
+pub const PAM_ACCT_EXPIRED: c_int = 13;
+pub const PAM_SESSION_ERR: c_int = 14;
+pub const PAM_CRED_UNAVAIL: c_int = 15;
+pub const PAM_CRED_EXPIRED: c_int = 16;
+pub const PAM_CRED_ERR: c_int = 17;
+pub const PAM_NO_MODULE_DATA: c_int = 18;
+pub const PAM_CONV_ERR: c_int = 19;
+pub const PAM_AUTHTOK_ERR: c_int = 20;
+pub const PAM_AUTHTOK_RECOVERY_ERR: c_int = 21;
+pub const PAM_AUTHTOK_LOCK_BUSY: c_int = 22;
+pub const PAM_AUTHTOK_DISABLE_AGING: c_int = 23;
+pub const PAM_TRY_AGAIN: c_int = 24;
+pub const PAM_IGNORE: c_int = 25;
+pub const PAM_ABORT: c_int = 26;
+pub const PAM_AUTHTOK_EXPIRED: c_int = 27;
+pub const PAM_MODULE_UNKNOWN: c_int = 28;
+
+// Flags
+pub const PAM_SILENT: c_int = 0x8000;
+pub const PAM_DISALLOW_NULL_AUTHTOK: c_int = 0x0001;
+pub const PAM_REFRESH_CRED: c_int = 0x0010;
+pub const PAM_CHANGE_EXPIRED_AUTHTOK: c_int = 0x0020;
+
+// Message styles
+pub const PAM_PROMPT_ECHO_OFF: c_int = 1;
+pub const PAM_PROMPT_ECHO_ON: c_int = 2;
+pub const PAM_ERROR_MSG: c_int = 3;
+pub const PAM_TEXT_INFO: c_int = 4;
+
+/// Maximum number of messages in a single conversation call.
+pub const PAM_MAX_NUM_MSG: c_int = 32;
+/// Maximum size of a response, including the null terminator.
+pub const PAM_MAX_RESP_SIZE: usize = 512;
+
+#[link(name = "pam")]
+extern "C" {
+    pub fn pam_start(service_name: *const c_char,
+                     user: *const c_char,
+                     pam_conversation: *const pam_conv,
+                     pamh: *mut *mut pam_handle_t) -> c_int;
+    pub fn pam_end(pamh: *mut pam_handle_t, pam_status: c_int) -> c_int;
+    pub fn pam_authenticate(pamh: *mut pam_handle_t, flags: c_int) -> c_int;
+    pub fn pam_acct_mgmt(pamh: *mut pam_handle_t, flags: c_int) -> c_int;
+    pub fn pam_setcred(pamh: *mut pam_handle_t, flags: c_int) -> c_int;
+    pub fn pam_chauthtok(pamh: *mut pam_handle_t, flags: c_int) -> c_int;
+    pub fn pam_strerror(pamh: *mut pam_handle_t,
+                        errnum: c_int) -> *const c_char;
+}
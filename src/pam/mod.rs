@@ -1,17 +1,426 @@
-//! Wrapper around authentication code written in C.
+//! Authentication through PAM.
 //!
-//! NOTE The reason such a security-sensitive module is written in C instead of
-//! Rust is because there are no good PAM crates available as of May 2017.
-//! If this changes in the future, this module should be removed in favor
-//! of using more battle-tested code.
+//! `Handle` is a safe wrapper around a PAM transaction. PAM drives the
+//! conversation through a `Conversation`: it can ask any number of
+//! questions and send informational messages before it decides.
+//!
+//! `PamAuthenticator` is the `Authenticator` used by default.
+
+mod ffi;
+
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use libc::{c_char, c_int, c_void, calloc, free, strlen};
+
+use auth::Authenticator;
+use secret::SecretBuffer;
+
+use self::ffi::*;
 
-use libc::c_char;
+/// Where Linux-PAM looks for the configuration of each service.
+const PAM_CONFIG_DIR: &'static str = "/etc/pam.d";
+
+/// Why PAM did not let the user in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PamError {
+    /// There is no configuration for the service in `/etc/pam.d`.
+    MissingService(String),
+    /// The service name or the username had an interior null byte.
+    InvalidArgument,
+    /// Wrong password, or some other wrong answer.
+    AuthError,
+    /// A module gave up after too many failed attempts.
+    MaxTries,
+    /// The user's account has expired.
+    AccountExpired,
+    /// The user has to change their password.
+    NewAuthTokenRequired,
+    /// The user is not known to the underlying authentication module.
+    UserUnknown,
+    /// The user is not allowed to authenticate.
+    PermissionDenied,
+    /// The application does not have enough credentials to authenticate.
+    CredentialsInsufficient,
+    /// The underlying authentication service can't be reached
+    /// (e.g the network is down).
+    AuthInfoUnavailable,
+    /// The user's credentials (e.g Kerberos tickets) could not be found.
+    CredentialsUnavailable,
+    /// The user's credentials have expired.
+    CredentialsExpired,
+    /// The user's credentials could not be refreshed.
+    CredentialsError,
+    /// The password could not be changed.
+    AuthTokenError,
+    /// The conversation failed, e.g because it was abandoned.
+    ConversationError,
+    /// Something is wrong with the PAM configuration or a module.
+    ServiceError,
+    /// A system call or memory allocation failed.
+    SystemError,
+    /// Any other return code, with the message libpam has for it.
+    Other(c_int, String)
+}
+
+impl PamError {
+    /// Determines if the user failed to get in, as opposed to something
+    /// going wrong that isn't down to them. Only these count as attempts.
+    pub fn is_failed_attempt(&self) -> bool {
+        matches!(*self, PamError::AuthError | PamError::UserUnknown |
+                        PamError::MaxTries | PamError::AccountExpired)
+    }
+
+    /// Converts the result of a libpam call.
+    fn from_code(handle: *mut pam_handle_t, code: c_int) -> Result<(), PamError> {
+        Err(match code {
+            PAM_SUCCESS => return Ok(()),
+            PAM_AUTH_ERR => PamError::AuthError,
+            PAM_MAXTRIES => PamError::MaxTries,
+            PAM_ACCT_EXPIRED => PamError::AccountExpired,
+            PAM_NEW_AUTHTOK_REQD |
+            PAM_AUTHTOK_EXPIRED => PamError::NewAuthTokenRequired,
+            PAM_USER_UNKNOWN => PamError::UserUnknown,
+            PAM_PERM_DENIED => PamError::PermissionDenied,
+            PAM_CRED_INSUFFICIENT => PamError::CredentialsInsufficient,
+            PAM_AUTHINFO_UNAVAIL => PamError::AuthInfoUnavailable,
+            PAM_CRED_UNAVAIL => PamError::CredentialsUnavailable,
+            PAM_CRED_EXPIRED => PamError::CredentialsExpired,
+            PAM_CRED_ERR => PamError::CredentialsError,
+            PAM_AUTHTOK_ERR | PAM_AUTHTOK_RECOVERY_ERR |
+            PAM_AUTHTOK_LOCK_BUSY |
+            PAM_AUTHTOK_DISABLE_AGING => PamError::AuthTokenError,
+            PAM_CONV_ERR | PAM_ABORT => PamError::ConversationError,
+            PAM_SERVICE_ERR | PAM_OPEN_ERR | PAM_SYMBOL_ERR |
+            PAM_MODULE_UNKNOWN => PamError::ServiceError,
+            PAM_SYSTEM_ERR | PAM_BUF_ERR => PamError::SystemError,
+            _ => {
+                let msg = unsafe {
+                    let msg = pam_strerror(handle, code);
+                    if msg.is_null() {
+                        String::new()
+                    } else {
+                        CStr::from_ptr(msg).to_string_lossy().into_owned()
+                    }
+                };
+                PamError::Other(code, msg)
+            }
+        })
+    }
+}
 
-extern "C" {
-    /// Checks to see if the username and password are valid through PAM.
+impl fmt::Display for PamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PamError::MissingService(ref service) => {
+                write!(f, "{}/{} is missing", PAM_CONFIG_DIR, service)
+            },
+            PamError::InvalidArgument => {
+                write!(f, "Invalid service or user name")
+            },
+            PamError::AuthError => write!(f, "Authentication failed"),
+            PamError::MaxTries => write!(f, "Too many failed attempts"),
+            PamError::AccountExpired => write!(f, "Account has expired"),
+            PamError::NewAuthTokenRequired => {
+                write!(f, "Password has to be changed")
+            },
+            PamError::UserUnknown => write!(f, "Unknown user"),
+            PamError::PermissionDenied => write!(f, "Permission denied"),
+            PamError::CredentialsInsufficient => {
+                write!(f, "Insufficient credentials")
+            },
+            PamError::AuthInfoUnavailable => {
+                write!(f, "Authentication service unavailable")
+            },
+            PamError::CredentialsUnavailable => {
+                write!(f, "Credentials unavailable")
+            },
+            PamError::CredentialsExpired => write!(f, "Credentials have expired"),
+            PamError::CredentialsError => {
+                write!(f, "Could not refresh credentials")
+            },
+            PamError::AuthTokenError => write!(f, "Could not change password"),
+            PamError::ConversationError => write!(f, "Conversation failed"),
+            PamError::ServiceError => write!(f, "PAM service error"),
+            PamError::SystemError => write!(f, "PAM system error"),
+            PamError::Other(code, ref msg) => {
+                write!(f, "PAM error {}: {}", code, msg)
+            }
+        }
+    }
+}
+
+impl Error for PamError {}
+
+/// The application's side of a PAM conversation.
+pub trait Conversation {
+    /// PAM wants an answer. If `echo` is false it is secret (e.g a password)
+    /// and must not be shown on screen.
     ///
-    /// Both strings should be null-terminated and non-null.
-    pub fn check_auth(username: *const c_char,
-                      password: *const c_char)
-                      -> bool;
+    /// Returning `Err` aborts the conversation.
+    fn prompt(&mut self, text: &str, echo: bool) -> Result<SecretBuffer, ()>;
+    /// Informational message, no answer expected.
+    fn info(&mut self, text: &str);
+    /// Error message, no answer expected.
+    fn error(&mut self, text: &str);
+}
+
+/// Authenticates users with the configuration of a PAM service.
+pub struct PamAuthenticator {
+    /// Name of the PAM service, i.e the file in `/etc/pam.d`.
+    service: String
+}
+
+impl PamAuthenticator {
+    pub fn new(service: &str) -> Self {
+        PamAuthenticator {
+            service: service.into()
+        }
+    }
+}
+
+impl Authenticator for PamAuthenticator {
+    fn authenticate(&self,
+                    username: &str,
+                    conversation: Box<dyn Conversation>) -> Result<(), PamError> {
+        let mut handle = Handle::start(&self.service, username, conversation)?;
+        handle.authenticate()?;
+        admit(&mut handle, username)
+    }
+
+    fn admit(&self,
+             username: &str,
+             conversation: Box<dyn Conversation>) -> Result<(), PamError> {
+        let mut handle = Handle::start(&self.service, username, conversation)?;
+        admit(&mut handle, username)
+    }
+}
+
+/// Checks that the account of a user who proved who they are is still
+/// allowed in, and refreshes their credentials.
+fn admit(handle: &mut Handle, username: &str) -> Result<(), PamError> {
+    // Authenticating doesn't mean the account is still allowed in.
+    match handle.acct_mgmt() {
+        Err(PamError::NewAuthTokenRequired) => {
+            handle.change_expired_authtok()?
+        },
+        result => result?
+    }
+    if let Err(err) = handle.refresh_credentials() {
+        // Refusing to unlock here would lock the user out of their session
+        // whenever e.g the Kerberos server can't be reached, so they just
+        // have to know about it before carrying on.
+        eprintln!("Could not refresh credentials for {}: {:?}", username, err);
+        let conversation = handle.conversation();
+        conversation.error(&err.to_string());
+        conversation.prompt("Press enter to unlock", false)
+            .map_err(|_| PamError::ConversationError)?;
+    }
+    Ok(())
+}
+
+/// A PAM transaction for a single user.
+///
+/// The transaction is ended when this is dropped.
+pub struct Handle {
+    handle: *mut pam_handle_t,
+    /// Result of the last libpam call, which is passed on to `pam_end`.
+    last_result: c_int,
+    /// Kept alive for as long as libpam might call back into it.
+    /// It's boxed twice so that libpam can be handed a thin pointer.
+    conversation: Box<Box<dyn Conversation>>
+}
+
+impl Handle {
+    /// Starts a PAM transaction for the user with the service's
+    /// configuration.
+    pub fn start(service: &str,
+                 username: &str,
+                 conversation: Box<dyn Conversation>) -> Result<Self, PamError> {
+        if Path::new(PAM_CONFIG_DIR).is_dir() &&
+            !Path::new(PAM_CONFIG_DIR).join(service).exists() {
+                // libpam would silently fall back on the "other" service.
+                return Err(PamError::MissingService(service.into()))
+            }
+        let c_service = CString::new(service)
+            .map_err(|_| PamError::InvalidArgument)?;
+        let c_username = CString::new(username)
+            .map_err(|_| PamError::InvalidArgument)?;
+        let mut conversation = Box::new(conversation);
+        let conv = pam_conv {
+            conv: converse,
+            appdata_ptr: &mut *conversation as *mut Box<dyn Conversation>
+                as *mut c_void
+        };
+        let mut handle = ptr::null_mut();
+        // libpam copies the `pam_conv` struct, so it doesn't have to outlive
+        // this call.
+        let result = unsafe {
+            pam_start(c_service.as_ptr(), c_username.as_ptr(),
+                      &conv, &mut handle)
+        };
+        if result != PAM_SUCCESS {
+            if !handle.is_null() {
+                unsafe { pam_end(handle, result) };
+            }
+            return Err(PamError::from_code(ptr::null_mut(), result)
+                       .err().unwrap_or(PamError::SystemError))
+        }
+        Ok(Handle {
+            handle,
+            last_result: result,
+            conversation
+        })
+    }
+
+    /// Checks that the user is who they say they are.
+    pub fn authenticate(&mut self) -> Result<(), PamError> {
+        // Not PAM_SILENT, informational messages are shown on the lock screen.
+        let result = unsafe {
+            pam_authenticate(self.handle, PAM_DISALLOW_NULL_AUTHTOK)
+        };
+        self.last_result = result;
+        PamError::from_code(self.handle, result)
+    }
+
+    /// Checks that the account is still valid, e.g that it hasn't expired
+    /// and that its password doesn't have to be changed.
+    pub fn acct_mgmt(&mut self) -> Result<(), PamError> {
+        let result = unsafe {
+            pam_acct_mgmt(self.handle, PAM_DISALLOW_NULL_AUTHTOK)
+        };
+        self.last_result = result;
+        PamError::from_code(self.handle, result)
+    }
+
+    /// Has the user change their expired password through the conversation.
+    pub fn change_expired_authtok(&mut self) -> Result<(), PamError> {
+        let result = unsafe {
+            pam_chauthtok(self.handle, PAM_CHANGE_EXPIRED_AUTHTOK)
+        };
+        self.last_result = result;
+        PamError::from_code(self.handle, result)
+    }
+
+    /// Refreshes the user's credentials, such as Kerberos tickets, which
+    /// may have expired while the screen was locked.
+    pub fn refresh_credentials(&mut self) -> Result<(), PamError> {
+        let result = unsafe { pam_setcred(self.handle, PAM_REFRESH_CRED) };
+        self.last_result = result;
+        PamError::from_code(self.handle, result)
+    }
+
+    /// The application's side of the conversation, for talking to the
+    /// user in between libpam calls.
+    pub fn conversation(&mut self) -> &mut dyn Conversation {
+        &mut **self.conversation
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { pam_end(self.handle, self.last_result) };
+    }
+}
+
+/// Conversation handler given to libpam, which passes each message on to
+/// the `Conversation`.
+extern "C" fn converse(num_msg: c_int,
+                       msg: *mut *const pam_message,
+                       resp: *mut *mut pam_response,
+                       appdata_ptr: *mut c_void) -> c_int {
+    if num_msg <= 0 || num_msg > PAM_MAX_NUM_MSG ||
+        msg.is_null() || resp.is_null() || appdata_ptr.is_null() {
+            return PAM_CONV_ERR
+        }
+    let conversation = unsafe { &mut *(appdata_ptr as *mut Box<dyn Conversation>) };
+    let num_msg = num_msg as usize;
+    let responses = unsafe {
+        calloc(num_msg, mem::size_of::<pam_response>()) as *mut pam_response
+    };
+    if responses.is_null() {
+        return PAM_BUF_ERR
+    }
+    // This is called from C, which a panic must not unwind into, and the
+    // `Conversation` could panic like any other code.
+    let answered = panic::catch_unwind(AssertUnwindSafe(|| {
+        answer_messages(&mut **conversation, msg, responses, num_msg)
+    })).unwrap_or(false);
+    if !answered {
+        unsafe { free_responses(responses, num_msg) };
+        return PAM_CONV_ERR
+    }
+    unsafe { *resp = responses };
+    PAM_SUCCESS
+}
+
+/// Puts the answer to each message into `responses`.
+///
+/// Returns false if any prompt went unanswered.
+fn answer_messages(conversation: &mut dyn Conversation,
+                   msg: *mut *const pam_message,
+                   responses: *mut pam_response,
+                   num_msg: usize) -> bool {
+    for i in 0..num_msg {
+        let message = unsafe { &**msg.add(i) };
+        let text = if message.msg.is_null() {
+            "".into()
+        } else {
+            unsafe { CStr::from_ptr(message.msg) }.to_string_lossy()
+        };
+        let answer = match message.msg_style {
+            PAM_PROMPT_ECHO_OFF => conversation.prompt(&text, false),
+            PAM_PROMPT_ECHO_ON => conversation.prompt(&text, true),
+            PAM_ERROR_MSG => {
+                conversation.error(&text);
+                continue
+            },
+            PAM_TEXT_INFO => {
+                conversation.info(&text);
+                continue
+            },
+            _ => Err(())
+        };
+        // This is the only copy of the answer that outlives the conversation,
+        // libpam wipes it once it's done with it.
+        let copy = match answer {
+            Ok(ref answer) if answer.len() < PAM_MAX_RESP_SIZE => {
+                unsafe { copy_response(answer.as_c_str()) }
+            },
+            _ => ptr::null_mut()
+        };
+        if copy.is_null() {
+            return false
+        }
+        unsafe { (*responses.add(i)).resp = copy };
+    }
+    true
+}
+
+/// Copies the answer into memory allocated with `malloc`, which libpam
+/// takes ownership of.
+unsafe fn copy_response(answer: &CStr) -> *mut c_char {
+    let len = answer.to_bytes_with_nul().len();
+    let copy = calloc(len, 1) as *mut c_char;
+    if !copy.is_null() {
+        ptr::copy_nonoverlapping(answer.as_ptr(), copy, len);
+    }
+    copy
+}
+
+/// Wipes and frees the responses, used when the conversation fails halfway.
+unsafe fn free_responses(responses: *mut pam_response, num_msg: usize) {
+    for i in 0..num_msg {
+        let resp = (*responses.add(i)).resp;
+        if !resp.is_null() {
+            ptr::write_bytes(resp, 0, strlen(resp));
+            free(resp as *mut c_void);
+        }
+    }
+    free(responses as *mut c_void);
 }
@@ -0,0 +1,195 @@
+//! Module containing the pointer handler, which sets the cursor over the lock
+//! surfaces and passes clicks on to the main loop.
+//!
+//! Without a cursor of its own, the pointer would keep whatever image it had
+//! when the screen was locked. Clicks can't be handled here, since which
+//! control was clicked depends on the surface, so they are sent to the main
+//! loop, which knows about all of them.
+
+use std::env;
+use std::sync::mpsc::Sender;
+
+use wayland_client::{cursor, EventQueueHandle, Proxy};
+use wayland_client::protocol::{wl_compositor, wl_pointer, wl_shm, wl_surface};
+
+use controls::Click;
+
+/// The Linux event code of the left mouse button.
+const BTN_LEFT: u32 = 0x110;
+/// Size of the cursor when `XCURSOR_SIZE` isn't set.
+const DEFAULT_CURSOR_SIZE: u32 = 24;
+
+/// The cursor image, loaded from the cursor theme.
+struct Cursor {
+    surface: wl_surface::WlSurface,
+    hotspot: (i32, i32),
+    /// Owns the buffer attached to the surface.
+    _theme: cursor::CursorTheme
+}
+
+pub struct Pointer {
+    /// What is shown as the cursor, it's hidden if there is nothing.
+    cursor: Option<Cursor>,
+    /// Used to load the cursor again when the scale changes.
+    compositor: wl_compositor::WlCompositor,
+    shm: wl_shm::WlShm,
+    hide_cursor: bool,
+    /// The scale the cursor is loaded at.
+    scale: u32,
+    /// The surface the pointer is over, if it's over one of ours.
+    focus: Option<wl_surface::WlSurface>,
+    x: f64,
+    y: f64,
+    clicks: Sender<Click>
+}
+
+impl Pointer {
+    pub fn new(compositor: &wl_compositor::WlCompositor,
+               shm: &wl_shm::WlShm,
+               hide_cursor: bool,
+               scale: u32,
+               clicks: Sender<Click>) -> Self {
+        let cursor = if hide_cursor {
+            None
+        } else {
+            load_cursor(compositor, shm, scale)
+        };
+        Pointer {
+            cursor,
+            compositor: compositor.clone().expect("Compositor was destroyed"),
+            shm: shm.clone().expect("Shm was destroyed"),
+            hide_cursor,
+            scale,
+            focus: None,
+            x: 0.0,
+            y: 0.0,
+            clicks
+        }
+    }
+
+    /// Loads the cursor again for outputs of a different scale, which is
+    /// shown the next time the pointer enters a lock surface.
+    pub fn set_scale(&mut self, scale: u32) {
+        if scale == self.scale {
+            return
+        }
+        self.scale = scale;
+        if !self.hide_cursor {
+            if let Some(cursor) = self.cursor.take() {
+                cursor.surface.destroy();
+            }
+            self.cursor = load_cursor(&self.compositor, &self.shm, scale);
+        }
+    }
+}
+
+/// Loads the arrow from the user's cursor theme, as set in `XCURSOR_THEME`
+/// and `XCURSOR_SIZE`.
+///
+/// The theme is loaded `scale` times as big, so the cursor is sharp on
+/// HiDPI outputs while staying the same size on screen.
+fn load_cursor(compositor: &wl_compositor::WlCompositor,
+               shm: &wl_shm::WlShm,
+               scale: u32) -> Option<Cursor> {
+    if !cursor::is_available() {
+        eprintln!("Warning: libwayland-cursor is not available, hiding the cursor");
+        return None
+    }
+    let name = env::var("XCURSOR_THEME").ok();
+    let size = env::var("XCURSOR_SIZE").ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_CURSOR_SIZE);
+    let theme = cursor::load_theme(name.as_ref().map(String::as_str), size * scale, shm);
+    let surface = compositor.create_surface();
+    let hotspot = {
+        let image = theme.get_cursor("left_ptr")?;
+        let buffer = image.frame_buffer(0)?;
+        let (width, height, x, y, _) = image.frame_info(0)?;
+        // Themes without an image that big give a smaller one, which can't
+        // always be scaled down.
+        let scale = if width % scale == 0 && height % scale == 0 {
+            scale
+        } else {
+            1
+        };
+        // Outputs only report a scale other than 1 if surfaces can be given
+        // scaled buffers.
+        if surface.version() >= 3 {
+            surface.set_buffer_scale(scale as i32);
+        }
+        surface.attach(Some(&*buffer), 0, 0);
+        surface.commit();
+        // The hotspot is in the surface's coordinates, not the buffer's.
+        ((x / scale) as i32, (y / scale) as i32)
+    };
+    Some(Cursor {
+        surface,
+        hotspot,
+        _theme: theme
+    })
+}
+
+impl wl_pointer::Handler for Pointer {
+    fn enter(&mut self,
+             _: &mut EventQueueHandle,
+             proxy: &wl_pointer::WlPointer,
+             serial: u32,
+             surface: &wl_surface::WlSurface,
+             surface_x: f64,
+             surface_y: f64) {
+        match self.cursor {
+            Some(ref cursor) => {
+                let (x, y) = cursor.hotspot;
+                proxy.set_cursor(serial, Some(&cursor.surface), x, y);
+            },
+            None => {
+                proxy.set_cursor(serial, None, 0, 0);
+            }
+        }
+        self.focus = surface.clone();
+        self.x = surface_x;
+        self.y = surface_y;
+    }
+
+    fn leave(&mut self,
+             _: &mut EventQueueHandle,
+             _: &wl_pointer::WlPointer,
+             _: u32,
+             _: &wl_surface::WlSurface) {
+        self.focus = None;
+    }
+
+    fn motion(&mut self,
+              _: &mut EventQueueHandle,
+              _: &wl_pointer::WlPointer,
+              _: u32,
+              surface_x: f64,
+              surface_y: f64) {
+        self.x = surface_x;
+        self.y = surface_y;
+    }
+
+    fn button(&mut self,
+              _: &mut EventQueueHandle,
+              _: &wl_pointer::WlPointer,
+              _: u32,
+              _: u32,
+              button: u32,
+              state: wl_pointer::ButtonState) {
+        match state {
+            wl_pointer::ButtonState::Pressed if button == BTN_LEFT => {},
+            _ => return
+        }
+        if let Some(surface) = self.focus.as_ref().and_then(|focus| focus.clone()) {
+            // The main loop only goes away once the screen is unlocked.
+            let _ = self.clicks.send(Click {
+                surface,
+                x: self.x,
+                y: self.y,
+                down: (self.x, self.y)
+            });
+        }
+    }
+}
+
+declare_handler!(Pointer, wl_pointer::Handler, wl_pointer::WlPointer);
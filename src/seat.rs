@@ -0,0 +1,232 @@
+//! Module containing the seat handler, which keeps track of the seat's
+//! keyboard, pointer and touch screen.
+//!
+//! Devices come and go while the screen is locked, like when a laptop is
+//! docked, so they're created whenever the seat says it has one and released
+//! when it no longer does. Seats can come and go as well, so `Seats` watches
+//! the globals `EnvHandler` keeps up to date for new and removed ones. Every
+//! keyboard of every seat types into the same `Input`.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+
+use wayland_client::{EnvHandler, EventQueue, EventQueueHandle, Proxy};
+use wayland_client::protocol::{wl_compositor, wl_keyboard, wl_pointer, wl_registry, wl_seat,
+                               wl_shm, wl_touch};
+
+use ::WaylandEnv;
+use controls::Click;
+use input::Input;
+use keyboard::Keyboard;
+use pointer::Pointer;
+use touch::Touch;
+
+pub struct Seat {
+    input: Arc<Mutex<Input>>,
+    /// The seat's keyboard, if it has one right now.
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    /// The `Keyboard` handler the seat's keyboards are registered to.
+    ///
+    /// Handlers can't be removed from the event queue, so a keyboard that
+    /// comes back reuses the handler of the one before it.
+    keyboard_id: usize,
+    /// The seat's pointer, if it has one right now.
+    pointer: Option<wl_pointer::WlPointer>,
+    /// The `Pointer` handler the seat's pointers are registered to.
+    pointer_id: usize,
+    /// The seat's touch screen, if it has one right now.
+    touch: Option<wl_touch::WlTouch>,
+    /// The `Touch` handler the seat's touch screens are registered to.
+    touch_id: usize
+}
+
+impl Seat {
+    pub fn new(input: Arc<Mutex<Input>>,
+               keyboard_id: usize,
+               pointer_id: usize,
+               touch_id: usize) -> Self {
+        Seat {
+            input,
+            keyboard: None,
+            keyboard_id,
+            pointer: None,
+            pointer_id,
+            touch: None,
+            touch_id
+        }
+    }
+
+    /// Releases all of the seat's devices, once the seat itself was removed.
+    ///
+    /// A removed seat doesn't have to say it has no devices anymore first.
+    fn remove(&mut self) {
+        self.release_keyboard();
+        self.release_pointer();
+        self.release_touch();
+    }
+
+    fn release_keyboard(&mut self) {
+        if let Some(keyboard) = self.keyboard.take() {
+            if keyboard.version() >= 3 {
+                keyboard.release();
+            }
+            // The keyboard won't say that the key was released.
+            self.input.lock().expect("Input was poisoned").stop_repeat();
+        }
+    }
+
+    fn release_pointer(&mut self) {
+        if let Some(pointer) = self.pointer.take() {
+            if pointer.version() >= 3 {
+                pointer.release();
+            }
+        }
+    }
+
+    fn release_touch(&mut self) {
+        if let Some(touch) = self.touch.take() {
+            if touch.version() >= 3 {
+                touch.release();
+            }
+        }
+    }
+}
+
+impl wl_seat::Handler for Seat {
+    fn capabilities(&mut self,
+                    evqh: &mut EventQueueHandle,
+                    proxy: &wl_seat::WlSeat,
+                    capabilities: wl_seat::Capability) {
+        let has_keyboard = capabilities.contains(wl_seat::Keyboard);
+        if has_keyboard && self.keyboard.is_none() {
+            let keyboard = proxy.get_keyboard().expect("Seat was destroyed");
+            evqh.register::<_, Keyboard>(&keyboard, self.keyboard_id);
+            self.keyboard = Some(keyboard);
+        } else if !has_keyboard {
+            self.release_keyboard();
+        }
+        let has_pointer = capabilities.contains(wl_seat::Pointer);
+        if has_pointer && self.pointer.is_none() {
+            let pointer = proxy.get_pointer().expect("Seat was destroyed");
+            evqh.register::<_, Pointer>(&pointer, self.pointer_id);
+            self.pointer = Some(pointer);
+        } else if !has_pointer {
+            self.release_pointer();
+        }
+        let has_touch = capabilities.contains(wl_seat::Touch);
+        if has_touch && self.touch.is_none() {
+            let touch = proxy.get_touch().expect("Seat was destroyed");
+            evqh.register::<_, Touch>(&touch, self.touch_id);
+            self.touch = Some(touch);
+        } else if !has_touch {
+            self.release_touch();
+        }
+    }
+}
+
+declare_handler!(Seat, wl_seat::Handler, wl_seat::WlSeat);
+
+/// The seats there are, and what the handlers of their devices are made
+/// with.
+pub struct Seats {
+    /// The names of the seats' globals, and the ids of their `Seat`
+    /// handlers.
+    seats: Vec<(u32, usize)>,
+    /// The `Pointer` handlers of every seat, removed or not.
+    pointer_ids: Vec<usize>,
+    input: Arc<Mutex<Input>>,
+    compositor: wl_compositor::WlCompositor,
+    shm: wl_shm::WlShm,
+    hide_cursor: bool,
+    /// The scale cursors are loaded at.
+    cursor_scale: u32,
+    clicks: Sender<Click>
+}
+
+impl Seats {
+    pub fn new(input: Arc<Mutex<Input>>,
+               compositor: wl_compositor::WlCompositor,
+               shm: wl_shm::WlShm,
+               hide_cursor: bool,
+               clicks: Sender<Click>) -> Self {
+        Seats {
+            seats: Vec::new(),
+            pointer_ids: Vec::new(),
+            input,
+            compositor,
+            shm,
+            hide_cursor,
+            cursor_scale: 1,
+            clicks
+        }
+    }
+
+    /// Sets up the seats that were advertised since the last time, and
+    /// releases the devices of the ones that were removed.
+    pub fn update(&mut self,
+                  registry: &wl_registry::WlRegistry,
+                  env_id: usize,
+                  event_queue: &mut EventQueue) {
+        let advertised: Vec<(u32, u32)> = {
+            let state = event_queue.state();
+            let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
+            env.globals().iter()
+                .filter(|&&(_, ref interface, _)| interface == "wl_seat")
+                .map(|&(name, _, version)| (name, version))
+                .collect()
+        };
+        let (kept, removed): (Vec<(u32, usize)>, Vec<(u32, usize)>) = self.seats.drain(..)
+            .partition(|&(name, _)| advertised.iter().any(|&(other, _)| other == name));
+        self.seats = kept;
+        {
+            let mut state = event_queue.state();
+            for (_, seat_id) in removed {
+                state.get_mut_handler::<Seat>(seat_id).remove();
+            }
+        }
+        for (name, version) in advertised {
+            if self.seats.iter().all(|&(other, _)| other != name) {
+                let seat_id = self.add(name, version, registry, event_queue);
+                self.seats.push((name, seat_id));
+            }
+        }
+    }
+
+    /// Loads the cursors of every pointer at a new scale.
+    pub fn set_cursor_scale(&mut self, scale: u32, event_queue: &mut EventQueue) {
+        if scale == self.cursor_scale {
+            return
+        }
+        self.cursor_scale = scale;
+        let mut state = event_queue.state();
+        for &pointer_id in &self.pointer_ids {
+            state.get_mut_handler::<Pointer>(pointer_id).set_scale(scale);
+        }
+    }
+
+    /// Binds the seat and sets up the handlers for its devices, which are
+    /// registered once the seat says it has them.
+    ///
+    /// Returns the id of the `Seat` handler.
+    fn add(&mut self,
+           name: u32,
+           version: u32,
+           registry: &wl_registry::WlRegistry,
+           event_queue: &mut EventQueue) -> usize {
+        // Releasing devices needs version 3, and key repeat info version 4.
+        let seat = registry.bind::<wl_seat::WlSeat>(cmp::min(version, 4), name);
+        let keyboard = Keyboard::new(self.input.clone())
+            .expect("Could not create input handler");
+        let keyboard_id = event_queue.add_handler(keyboard);
+        let pointer = Pointer::new(&self.compositor, &self.shm, self.hide_cursor,
+                                   self.cursor_scale, self.clicks.clone());
+        let pointer_id = event_queue.add_handler(pointer);
+        self.pointer_ids.push(pointer_id);
+        let touch_id = event_queue.add_handler(Touch::new(self.clicks.clone()));
+        let seat_id = event_queue.add_handler(Seat::new(self.input.clone(), keyboard_id,
+                                                        pointer_id, touch_id));
+        event_queue.register::<_, Seat>(&seat, seat_id);
+        seat_id
+    }
+}
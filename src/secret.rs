@@ -0,0 +1,177 @@
+//! Storage for what the user types in, which is usually a password.
+//!
+//! `SecretBuffer` never reallocates, so no stale copies are left behind in
+//! freed memory as it grows. It is locked into memory so that it can't be
+//! swapped out, and it is zeroed as soon as it is cleared or dropped.
+//!
+//! Every buffer is mapped on its own page. `mlock` works on whole pages and
+//! doesn't nest, so unlocking a buffer that shares a page with other memory
+//! would unlock that memory too, including other buffers.
+
+use std::ffi::CStr;
+use std::ptr;
+use std::slice;
+use std::str;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use libc::{self, c_void};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How many bytes a `SecretBuffer` can hold.
+///
+/// PAM refuses responses that are longer than this anyways.
+pub const CAPACITY: usize = 511;
+
+pub struct SecretBuffer {
+    /// The page the buffer is mapped on, `CAPACITY + 1` bytes of which are
+    /// used. Always null-terminated, so that it can be handed to C as-is.
+    page: *mut u8,
+    page_size: usize,
+    len: usize,
+    /// Whether `mlock` succeeded.
+    locked: bool
+}
+
+// The page is owned by the buffer, like a `Box` would be.
+unsafe impl Send for SecretBuffer {}
+
+impl SecretBuffer {
+    pub fn new() -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        assert!(page_size > CAPACITY, "Pages are too small for a password buffer");
+        let page = unsafe {
+            libc::mmap(ptr::null_mut(), page_size, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+        };
+        if page == libc::MAP_FAILED {
+            panic!("Could not map password buffer");
+        }
+        // Anonymous mappings are zeroed already.
+        let locked = unsafe { libc::mlock(page, page_size) == 0 };
+        SecretBuffer {
+            page: page as *mut u8,
+            page_size,
+            len: 0,
+            locked
+        }
+    }
+
+    /// Whether the buffer is locked into memory.
+    ///
+    /// Locking only fails if locked memory is limited, so if it failed for
+    /// one buffer it fails for all of them.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.page, CAPACITY + 1) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.page, CAPACITY + 1) }
+    }
+
+    /// Appends the text, if there is enough room left for all of it.
+    ///
+    /// Returns false if nothing was added.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        let text = text.as_bytes();
+        if text.contains(&0) || self.len + text.len() > CAPACITY {
+            return false
+        }
+        let len = self.len;
+        self.bytes_mut()[len..len + text.len()].copy_from_slice(text);
+        self.len += text.len();
+        true
+    }
+
+    /// Removes the last grapheme cluster, i.e what the user sees as one
+    /// character even if it's made of several code points.
+    ///
+    /// Returns false if there was nothing to remove.
+    pub fn pop(&mut self) -> bool {
+        let new_len = match self.as_str().grapheme_indices(true).next_back() {
+            Some((i, _)) => i,
+            None => return false
+        };
+        self.truncate(new_len);
+        true
+    }
+
+    /// Removes the last word, and any whitespace after it.
+    pub fn pop_word(&mut self) {
+        let new_len = {
+            let text = self.as_str().trim_end();
+            match text.char_indices().rev().find(|&(_, chr)| chr.is_whitespace()) {
+                Some((i, chr)) => i + chr.len_utf8(),
+                None => 0
+            }
+        };
+        self.truncate(new_len);
+    }
+
+    /// Removes everything after the first `len` bytes, zeroing it.
+    ///
+    /// `len` must be on a character boundary.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return
+        }
+        assert!(self.as_str().is_char_boundary(len));
+        let old_len = self.len;
+        zero(&mut self.bytes_mut()[len..old_len]);
+        self.len = len;
+    }
+
+    /// Zeroes the buffer.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from `&str`s, and only cut on char boundaries.
+        unsafe { str::from_utf8_unchecked(&self.bytes()[..self.len]) }
+    }
+
+    /// The contents as a C string, without copying them.
+    pub fn as_c_str(&self) -> &CStr {
+        // Never contains an interior null, and the byte after the end is
+        // always zero.
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.bytes()[..self.len + 1]) }
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        zero(self.bytes_mut());
+        // Unmapping the page unlocks it, and nothing else is on it.
+        unsafe {
+            libc::munmap(self.page as *mut c_void, self.page_size);
+        }
+    }
+}
+
+/// Zeroes the string's memory before freeing it.
+///
+/// For the short-lived copies of the input that other libraries hand us.
+pub fn wipe(text: String) {
+    let mut bytes = text.into_bytes();
+    zero(&mut bytes);
+}
+
+/// Zeroes the bytes in a way the compiler won't optimize out.
+pub fn zero(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
@@ -0,0 +1,178 @@
+//! Slows down password guessing.
+//!
+//! Every failed attempt makes the user wait longer before the next one, and
+//! enough failures in a row lock them out for a while. The failures can be
+//! saved to a file so that restarting `wc-lock` doesn't reset them.
+
+use std::cmp;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the user has to wait after failing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Policy {
+    /// Wait after the first failure, doubled for every failure after that.
+    pub base_delay: Duration,
+    /// The longest the wait can get, outside of lockouts.
+    pub max_delay: Duration,
+    /// Number of failures in a row that triggers a lockout, 0 to disable.
+    pub lockout_after: u32,
+    /// How long a lockout lasts.
+    pub lockout_duration: Duration
+}
+
+impl Policy {
+    /// How long to wait after this many failures in a row.
+    fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::from_secs(0)
+        }
+        if self.is_lockout(failures) {
+            return self.lockout_duration
+        }
+        let factor = 1u32.checked_shl(failures - 1).unwrap_or(u32::MAX);
+        let delay = self.base_delay.checked_mul(factor).unwrap_or(self.max_delay);
+        cmp::min(delay, self.max_delay)
+    }
+
+    /// The longest any single wait can be.
+    fn longest_delay(&self) -> Duration {
+        if self.lockout_after != 0 {
+            cmp::max(self.max_delay, self.lockout_duration)
+        } else {
+            self.max_delay
+        }
+    }
+
+    /// Determines if this many failures in a row means a lockout.
+    fn is_lockout(&self, failures: u32) -> bool {
+        self.lockout_after != 0 && failures != 0 &&
+            failures % self.lockout_after == 0
+    }
+}
+
+pub struct Throttle {
+    policy: Policy,
+    /// Failed attempts in a row.
+    failures: u32,
+    /// No attempts are allowed before this time.
+    blocked_until: SystemTime,
+    /// Where the failures are saved, if they are.
+    state_file: Option<PathBuf>
+}
+
+impl Throttle {
+    /// Loads the failures from the state file, if there is one.
+    pub fn new(policy: Policy, state_file: Option<PathBuf>) -> Self {
+        let mut throttle = Throttle {
+            policy,
+            failures: 0,
+            blocked_until: UNIX_EPOCH,
+            state_file
+        };
+        throttle.load();
+        throttle
+    }
+
+    /// Failed attempts in a row.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// How long until the next attempt is allowed, if it isn't yet.
+    pub fn remaining(&self) -> Option<Duration> {
+        let remaining = self.blocked_until.duration_since(SystemTime::now())
+            .ok()?;
+        // Don't let the clock being turned back lock the user out for longer.
+        Some(cmp::min(remaining, self.policy.longest_delay()))
+    }
+
+    /// Determines if the user is waiting out a lockout, rather than a delay.
+    pub fn is_locked_out(&self) -> bool {
+        self.remaining().is_some() && self.policy.is_lockout(self.failures)
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        self.blocked_until = SystemTime::now() + self.policy.delay(self.failures);
+        self.save();
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.blocked_until = UNIX_EPOCH;
+        if let Some(ref path) = self.state_file {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Reads the state file, which is "<failures> <blocked until>" with the
+    /// time in seconds since the epoch.
+    fn load(&mut self) {
+        let path = match self.state_file {
+            Some(ref path) => path,
+            None => return
+        };
+        let mut contents = String::new();
+        if File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+                return
+            }
+        let mut fields = contents.split_whitespace()
+            .map(|field| field.parse::<u64>());
+        match (fields.next(), fields.next()) {
+            (Some(Ok(failures)), Some(Ok(blocked_until))) => {
+                self.failures = cmp::min(failures, u32::MAX as u64) as u32;
+                self.blocked_until = UNIX_EPOCH + Duration::from_secs(blocked_until);
+            },
+            _ => eprintln!("Ignoring malformed failure count in {}",
+                           path.display())
+        }
+    }
+
+    fn save(&self) {
+        let path = match self.state_file {
+            Some(ref path) => path,
+            None => return
+        };
+        // Rounded up, so that reloading never shortens the wait.
+        let blocked_until = self.blocked_until.duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() + 1)
+            .unwrap_or(0);
+        let contents = format!("{} {}\n", self.failures, blocked_until);
+        if let Err(err) = write_state(path, &contents) {
+            eprintln!("Could not save failure count to {}: {}",
+                      path.display(), err);
+        }
+    }
+}
+
+/// Default location of the state file, in the user's cache directory.
+pub fn default_state_file() -> Option<PathBuf> {
+    state_file("failures")
+}
+
+/// Where a file that outlives `wc-lock` is kept, in the user's cache
+/// directory.
+pub fn state_file(name: &str) -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|cache| cache.join("wc-lock").join(name))
+}
+
+/// Replaces the contents of a state file, which only the user can read.
+pub fn write_state(path: &Path, contents: &str) -> io::Result<()> {
+    path.parent().map_or(Ok(()), fs::create_dir_all)?;
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
@@ -0,0 +1,558 @@
+//! Time-based one-time codes (RFC 6238), checked after the password as a
+//! second factor.
+//!
+//! Each user's secret is read from a file, base32 encoded like most
+//! authenticator apps show it. The file is only trusted if nobody but the
+//! user running `wc-lock` (or root) could have written it, and nobody else
+//! can read it.
+//!
+//! A code is only good once: the time step of the last one that let each
+//! user in is saved, so someone who saw it typed in can't use it to unlock
+//! the next lock screen either.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libc::{getpwnam, getuid};
+
+use secret;
+use throttle;
+
+/// Seconds each code is valid for.
+const STEP: u64 = 30;
+/// Digits in a code.
+const DIGITS: u32 = 6;
+/// How many steps a code can be off by, to make up for clocks drifting apart.
+const SKEW: u64 = 1;
+
+/// Where the users' secrets are kept.
+#[derive(Clone, Debug)]
+pub enum Secrets {
+    /// `~/.config/wc-lock/totp` in each user's home.
+    Home,
+    /// A file named after each user in this directory.
+    Dir(PathBuf)
+}
+
+impl Secrets {
+    fn path(&self, username: &str) -> Result<PathBuf, TotpError> {
+        match *self {
+            Secrets::Home => home_dir(username)
+                .map(|home| home.join(".config/wc-lock/totp"))
+                .ok_or_else(|| TotpError::NoHome(username.into())),
+            Secrets::Dir(ref dir) => Ok(dir.join(username))
+        }
+    }
+
+    /// Loads the user's secret, checking that it's safe to use.
+    pub fn load(&self, username: &str) -> Result<Totp, TotpError> {
+        let path = self.path(username)?;
+        let insecure = |reason: &str| {
+            Err(TotpError::Insecure(format!("{} {}", path.display(), reason)))
+        };
+        let metadata = path.symlink_metadata()
+            .map_err(|err| TotpError::Io(path.clone(), err))?;
+        if !metadata.file_type().is_file() {
+            return insecure("is not a regular file")
+        }
+        let uid = unsafe { getuid() };
+        if metadata.uid() != uid && metadata.uid() != 0 {
+            return insecure("is owned by someone else")
+        }
+        if metadata.permissions().mode() & 0o077 != 0 {
+            return insecure("can be accessed by other users")
+        }
+        if let Some(dir) = path.parent() {
+            let dir_metadata = dir.metadata()
+                .map_err(|err| TotpError::Io(dir.into(), err))?;
+            if dir_metadata.uid() != uid && dir_metadata.uid() != 0 ||
+                dir_metadata.permissions().mode() & 0o022 != 0 {
+                    return Err(TotpError::Insecure(
+                        format!("{} can be written to by other users", dir.display())))
+                }
+        }
+        let mut contents = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| TotpError::Io(path.clone(), err))?;
+        let key = contents.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .and_then(decode_base32)
+            .filter(|key| !key.is_empty());
+        secret::wipe(contents);
+        key.map(|key| Totp::new(key, DIGITS))
+            .ok_or(TotpError::Malformed(path))
+    }
+}
+
+/// Why a user's secret could not be used.
+#[derive(Debug)]
+pub enum TotpError {
+    /// Could not find the user's home directory.
+    NoHome(String),
+    Io(PathBuf, io::Error),
+    /// Someone else could have read or changed the secret.
+    Insecure(String),
+    /// The file doesn't contain a base32 secret.
+    Malformed(PathBuf)
+}
+
+impl fmt::Display for TotpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TotpError::NoHome(ref username) => {
+                write!(f, "Could not find the home directory of {}", username)
+            },
+            TotpError::Io(ref path, ref err) => {
+                write!(f, "Could not read {}: {}", path.display(), err)
+            },
+            TotpError::Insecure(ref reason) => {
+                write!(f, "Refusing to use one-time code secret: {}", reason)
+            },
+            TotpError::Malformed(ref path) => {
+                write!(f, "{} does not contain a base32 secret", path.display())
+            }
+        }
+    }
+}
+
+impl Error for TotpError {}
+
+/// Generates and checks the codes for one secret.
+pub struct Totp {
+    key: Vec<u8>,
+    digits: u32
+}
+
+impl Totp {
+    pub fn new(key: Vec<u8>, digits: u32) -> Self {
+        Totp { key, digits }
+    }
+
+    /// The code for a number of seconds since the epoch.
+    pub fn code_at(&self, time: u64) -> u32 {
+        self.hotp(time / STEP)
+    }
+
+    /// Checks the code the user typed in against the current time.
+    ///
+    /// Returns the time step of the code if it matched. Codes from steps up
+    /// to `last_used`, the step of the last code that got the user in, are
+    /// turned down, so a code someone saw being typed in can't be reused.
+    pub fn verify(&self, code: &str, last_used: Option<u64>) -> Option<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+        self.verify_at(code, now, last_used)
+    }
+
+    fn verify_at(&self, code: &str, time: u64, last_used: Option<u64>) -> Option<u64> {
+        let code = code.trim();
+        if code.len() != self.digits as usize ||
+            !code.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None
+            }
+        let code: u32 = match code.parse() {
+            Ok(code) => code,
+            Err(_) => return None
+        };
+        let counter = time / STEP;
+        let mut matched = None;
+        // Every candidate is checked, so how long this takes doesn't give
+        // away which one matched.
+        for counter in counter.saturating_sub(SKEW)..counter + SKEW + 1 {
+            let fresh = last_used.map_or(true, |last_used| counter > last_used);
+            if self.hotp(counter) == code && fresh {
+                matched = Some(counter);
+            }
+        }
+        matched
+    }
+
+    /// HMAC-based one-time password, RFC 4226.
+    fn hotp(&self, counter: u64) -> u32 {
+        let mac = hmac_sha1(&self.key, &counter.to_be_bytes());
+        let offset = (mac[19] & 0xf) as usize;
+        let truncated = (mac[offset] as u32 & 0x7f) << 24 |
+            (mac[offset + 1] as u32) << 16 |
+            (mac[offset + 2] as u32) << 8 |
+            mac[offset + 3] as u32;
+        truncated % 10u32.pow(self.digits)
+    }
+}
+
+impl Drop for Totp {
+    fn drop(&mut self) {
+        secret::zero(&mut self.key);
+    }
+}
+
+/// The time steps of the last codes that let each user in.
+pub struct UsedCodes {
+    steps: HashMap<String, u64>,
+    /// Where the steps are saved, if they are.
+    file: Option<PathBuf>
+}
+
+impl UsedCodes {
+    /// Loads the steps from the file, if there is one.
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let mut used = UsedCodes {
+            steps: HashMap::new(),
+            file
+        };
+        used.load();
+        used
+    }
+
+    /// The step of the last code that let the user in, if one did.
+    pub fn last(&self, username: &str) -> Option<u64> {
+        self.steps.get(username).cloned()
+    }
+
+    /// Remembers that the code of a step let the user in.
+    pub fn record(&mut self, username: &str, step: u64) {
+        self.steps.insert(username.into(), step);
+        self.save();
+    }
+
+    /// Reads the file, which has a "<username> <step>" line for each user.
+    fn load(&mut self) {
+        let path = match self.file {
+            Some(ref path) => path,
+            None => return
+        };
+        let mut contents = String::new();
+        if File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+                return
+            }
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next().map(str::parse::<u64>)) {
+                (Some(username), Some(Ok(step))) => {
+                    self.steps.insert(username.into(), step);
+                },
+                _ => eprintln!("Ignoring malformed used code in {}", path.display())
+            }
+        }
+    }
+
+    fn save(&self) {
+        let path = match self.file {
+            Some(ref path) => path,
+            None => return
+        };
+        let contents: String = self.steps.iter()
+            .map(|(username, step)| format!("{} {}\n", username, step))
+            .collect();
+        if let Err(err) = throttle::write_state(path, &contents) {
+            eprintln!("Could not save used codes to {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Default location of the used codes, next to the failure count.
+pub fn default_used_codes_file() -> Option<PathBuf> {
+    throttle::state_file("used-codes")
+}
+
+/// Decodes RFC 4648 base32, ignoring case, spaces and padding.
+fn decode_base32(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for chr in text.chars().filter(|&chr| chr != ' ' && chr != '=') {
+        let value = match chr.to_ascii_uppercase() {
+            chr @ 'A'..='Z' => chr as u32 - 'A' as u32,
+            chr @ '2'..='7' => chr as u32 - '2' as u32 + 26,
+            _ => return None
+        };
+        buffer = buffer << 5 | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    // Allocated up front, so that growing them doesn't leave copies of the
+    // key behind.
+    let mut inner = Vec::with_capacity(BLOCK_SIZE + message.len());
+    inner.extend(block.iter().map(|byte| byte ^ 0x36));
+    inner.extend_from_slice(message);
+    let mut outer = Vec::with_capacity(BLOCK_SIZE + 20);
+    outer.extend(block.iter().map(|byte| byte ^ 0x5c));
+    outer.extend_from_slice(&sha1(&inner));
+    secret::zero(&mut block);
+    secret::zero(&mut inner);
+    let mac = sha1(&outer);
+    secret::zero(&mut outer);
+    mac
+}
+
+/// SHA-1, which is still what HOTP and every authenticator app use.
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = Vec::with_capacity(message.len() + 72);
+    padded.extend_from_slice(message);
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    let bit_len = (message.len() as u64).wrapping_mul(8);
+    padded.extend_from_slice(&bit_len.to_be_bytes());
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6)
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e)
+                .wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, new) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *value = value.wrapping_add(*new);
+        }
+    }
+    secret::zero(&mut padded);
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Looks up the home directory of a user.
+fn home_dir(username: &str) -> Option<PathBuf> {
+    let username = CString::new(username).ok()?;
+    unsafe {
+        let passwd = getpwnam(username.as_ptr());
+        if passwd.is_null() || (*passwd).pw_dir.is_null() {
+            return None
+        }
+        let dir = CStr::from_ptr((*passwd).pw_dir).to_string_lossy().into_owned();
+        Some(PathBuf::from(dir))
+    }
+}
+
+/// What tests of the modules using one-time codes need.
+#[cfg(test)]
+pub mod fixtures {
+    use std::env;
+    use std::fs::{self, DirBuilder, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A directory only the current user can get into, removed on drop.
+    pub struct TestDir(pub PathBuf);
+
+    impl TestDir {
+        pub fn new(name: &str) -> Self {
+            let dir = env::temp_dir()
+                .join(format!("wc-lock-totp-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            DirBuilder::new().mode(0o700).create(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        pub fn write(&self, name: &str, contents: &str, mode: u32) {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode)
+                .open(self.0.join(name))
+                .unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            // The umask might have taken some permissions away.
+            fs::set_permissions(self.0.join(name), fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::fixtures::TestDir;
+    use super::{decode_base32, hmac_sha1, sha1, Secrets, Totp, TotpError, UsedCodes, STEP};
+
+    const RFC_SECRET: &'static [u8] = b"12345678901234567890";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha1_digests() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn hmac_sha1_rfc2202() {
+        assert_eq!(hex(&hmac_sha1(&[0x0b; 20], b"Hi There")),
+                   "b617318655057264e28bc0b6fb378c8ef146be00");
+        assert_eq!(hex(&hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+                   "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+        assert_eq!(hex(&hmac_sha1(&[0xaa; 80],
+                                  b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "aa4ae5e15272d00e95705637ce8a3b55ed402112");
+    }
+
+    #[test]
+    fn hotp_rfc4226() {
+        let totp = Totp::new(RFC_SECRET.to_vec(), 6);
+        let expected = [755224, 287082, 359152, 969429, 338314,
+                        254676, 287922, 162583, 399871, 520489];
+        for (counter, &code) in expected.iter().enumerate() {
+            assert_eq!(totp.hotp(counter as u64), code);
+        }
+    }
+
+    #[test]
+    fn totp_rfc6238() {
+        let totp = Totp::new(RFC_SECRET.to_vec(), 8);
+        let vectors = [(59, "94287082"),
+                       (1111111109, "07081804"),
+                       (1111111111, "14050471"),
+                       (1234567890, "89005924"),
+                       (2000000000, "69279037"),
+                       (20000000000, "65353130")];
+        for &(time, code) in vectors.iter() {
+            assert_eq!(format!("{:08}", totp.code_at(time)), code);
+            assert_eq!(totp.verify_at(code, time, None), Some(time / STEP));
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        let totp = Totp::new(RFC_SECRET.to_vec(), 8);
+        let step = 1111111109 / STEP;
+        assert_eq!(totp.verify_at("07081804", 1111111109 + 30, None), Some(step));
+        assert_eq!(totp.verify_at("07081804", 1111111109 - 30, None), Some(step));
+        assert_eq!(totp.verify_at("07081804", 1111111109 + 60, None), None);
+        assert_eq!(totp.verify_at("7081804", 1111111109, None), None);
+        assert_eq!(totp.verify_at("0708180a", 1111111109, None), None);
+        assert_eq!(totp.verify_at("", 1111111109, None), None);
+    }
+
+    #[test]
+    fn used_codes_are_turned_down() {
+        let totp = Totp::new(RFC_SECRET.to_vec(), 8);
+        let step = 1111111109 / STEP;
+        assert_eq!(totp.verify_at("07081804", 1111111109, Some(step)), None);
+        assert_eq!(totp.verify_at("07081804", 1111111109 + 30, Some(step)), None);
+        assert_eq!(totp.verify_at("07081804", 1111111109, Some(step + 1)), None);
+        assert_eq!(totp.verify_at("07081804", 1111111109, Some(step - 1)), Some(step));
+        // The next code is still fine after this one was used.
+        let next = format!("{:08}", totp.code_at(1111111109 + 30));
+        assert_eq!(totp.verify_at(&next, 1111111109 + 30, Some(step)), Some(step + 1));
+    }
+
+    #[test]
+    fn base32() {
+        assert_eq!(decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(),
+                   RFC_SECRET.to_vec());
+        assert_eq!(decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(),
+                   RFC_SECRET.to_vec());
+        assert_eq!(decode_base32("MZXW6===").unwrap(), b"foo".to_vec());
+        assert!(decode_base32("GEZ1").is_none());
+    }
+
+    #[test]
+    fn secret_files_are_checked() {
+        let dir = TestDir::new("files");
+        let secrets = Secrets::Dir(dir.0.clone());
+        dir.write("alice", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o600);
+        dir.write("bob", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o644);
+        dir.write("carol", "not base32!\n", 0o600);
+        let totp = secrets.load("alice").unwrap();
+        assert_eq!(totp.code_at(59), 287082);
+        match secrets.load("bob") {
+            Err(TotpError::Insecure(_)) => {},
+            _ => panic!("Used a secret other users can read")
+        }
+        match secrets.load("carol") {
+            Err(TotpError::Malformed(_)) => {},
+            _ => panic!("Used a malformed secret")
+        }
+        match secrets.load("dave") {
+            Err(TotpError::Io(..)) => {},
+            _ => panic!("Used a secret that doesn't exist")
+        }
+    }
+
+    #[test]
+    fn used_codes_are_saved() {
+        let dir = TestDir::new("used");
+        let file = dir.0.join("used-codes");
+        let mut used = UsedCodes::new(Some(file.clone()));
+        assert_eq!(used.last("alice"), None);
+        used.record("alice", 5);
+        used.record("bob", 7);
+        used.record("alice", 6);
+        let used = UsedCodes::new(Some(file.clone()));
+        assert_eq!(used.last("alice"), Some(6));
+        assert_eq!(used.last("bob"), Some(7));
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn shared_directories_are_refused() {
+        let dir = TestDir::new("shared");
+        dir.write("alice", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o600);
+        fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o777)).unwrap();
+        match Secrets::Dir(dir.0.clone()).load("alice") {
+            Err(TotpError::Insecure(_)) => {},
+            _ => panic!("Used a secret in a directory anyone can write to")
+        }
+    }
+}
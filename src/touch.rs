@@ -0,0 +1,93 @@
+//! Module containing the touch handler, which turns taps on the lock surfaces
+//! into clicks for the main loop.
+//!
+//! A tap only counts if the finger is lifted over the control it touched, so
+//! a finger that slides off a key, or onto one, doesn't press it.
+
+use std::sync::mpsc::Sender;
+
+use wayland_client::EventQueueHandle;
+use wayland_client::protocol::{wl_surface, wl_touch};
+
+use controls::Click;
+
+/// A finger that is touching one of our surfaces.
+struct Point {
+    id: i32,
+    surface: wl_surface::WlSurface,
+    x: f64,
+    y: f64,
+    /// Where the finger touched the surface.
+    down: (f64, f64)
+}
+
+pub struct Touch {
+    points: Vec<Point>,
+    clicks: Sender<Click>
+}
+
+impl Touch {
+    pub fn new(clicks: Sender<Click>) -> Self {
+        Touch {
+            points: Vec::new(),
+            clicks
+        }
+    }
+}
+
+impl wl_touch::Handler for Touch {
+    fn down(&mut self,
+            _: &mut EventQueueHandle,
+            _: &wl_touch::WlTouch,
+            _: u32,
+            _: u32,
+            surface: &wl_surface::WlSurface,
+            id: i32,
+            x: f64,
+            y: f64) {
+        if let Some(surface) = surface.clone() {
+            self.points.retain(|point| point.id != id);
+            self.points.push(Point { id, surface, x, y, down: (x, y) });
+        }
+    }
+
+    fn up(&mut self,
+          _: &mut EventQueueHandle,
+          _: &wl_touch::WlTouch,
+          _: u32,
+          _: u32,
+          id: i32) {
+        if let Some(index) = self.points.iter().position(|point| point.id == id) {
+            let point = self.points.remove(index);
+            // The main loop only goes away once the screen is unlocked.
+            let _ = self.clicks.send(Click {
+                surface: point.surface,
+                x: point.x,
+                y: point.y,
+                down: point.down
+            });
+        }
+    }
+
+    fn motion(&mut self,
+              _: &mut EventQueueHandle,
+              _: &wl_touch::WlTouch,
+              _: u32,
+              id: i32,
+              x: f64,
+              y: f64) {
+        if let Some(point) = self.points.iter_mut().find(|point| point.id == id) {
+            point.x = x;
+            point.y = y;
+        }
+    }
+
+    fn cancel(&mut self,
+              _: &mut EventQueueHandle,
+              _: &wl_touch::WlTouch) {
+        // The compositor took over, none of the touches count.
+        self.points.clear();
+    }
+}
+
+declare_handler!(Touch, wl_touch::Handler, wl_touch::WlTouch);
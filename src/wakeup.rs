@@ -0,0 +1,61 @@
+//! Lets other threads wake up the main loop, which otherwise only wakes up
+//! when the compositor sends us something.
+//!
+//! This is a pipe, the main loop polls the read end along with the
+//! Wayland display and worker threads write a byte to the other end.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::Arc;
+
+use libc::{pipe2, O_CLOEXEC, O_NONBLOCK};
+
+/// The end of the pipe the main loop waits on.
+pub struct Wakeup {
+    pipe: File
+}
+
+/// Handle that can wake up the main loop from any thread.
+#[derive(Clone)]
+pub struct Waker {
+    pipe: Arc<File>
+}
+
+impl Wakeup {
+    pub fn new() -> io::Result<(Wakeup, Waker)> {
+        let mut fds = [0; 2];
+        if unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC | O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        let (read, write) = unsafe {
+            (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))
+        };
+        Ok((Wakeup { pipe: read }, Waker { pipe: Arc::new(write) }))
+    }
+
+    /// Empties the pipe, so that polling on it blocks again.
+    pub fn drain(&mut self) {
+        let mut buf = [0u8; 64];
+        // Stops on `WouldBlock` once it's empty.
+        while let Ok(read) = self.pipe.read(&mut buf) {
+            if read == 0 {
+                break
+            }
+        }
+    }
+}
+
+impl AsRawFd for Wakeup {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pipe.as_raw_fd()
+    }
+}
+
+impl Waker {
+    /// Wakes up the main loop.
+    pub fn wake(&self) {
+        // If the pipe is full the main loop is going to wake up anyways.
+        let _ = (&*self.pipe).write(&[1]);
+    }
+}
@@ -7,24 +7,60 @@ use wayland_client::{self, EventQueueHandle, EnvHandler, Proxy};
 use wayland_client::protocol::{wl_shm, wl_shell_surface, wl_buffer, wl_output,
                                wl_surface};
 use wayland_client::protocol::wl_shell_surface::FullscreenMethod;
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{ByteOrder, NativeEndian, WriteBytesExt};
 use tempfile;
 
 use ::WaylandEnv;
 use ::color::Color;
+use ::controls::{self, Control};
+use ::font;
+
+/// How an output is rotated and flipped, counter-clockwise like in
+/// `wl_output`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Transform {
+    Normal,
+    Rotated90,
+    Rotated180,
+    Rotated270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270
+}
+
+impl Transform {
+    /// Whether the output is on its side, so its width and height are
+    /// swapped on screen.
+    fn is_sideways(self) -> bool {
+        matches!(self, Transform::Rotated90 | Transform::Rotated270 |
+                       Transform::Flipped90 | Transform::Flipped270)
+    }
+}
 
 /// Used to know how big to make the surface.
+///
+/// `w` and `h` are in pixels, the size of the output's mode. The buffer has
+/// that size too, and is in the output's own orientation, like the screen
+/// that `Scrape` returns. Everything drawn on the surface is laid out in
+/// logical units instead, which are `buffer_scale()` pixels wide and upright
+/// on screen, so it's the same size on HiDPI outputs and the right way up on
+/// rotated ones.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Resolution {
     pub w: u32,
-    pub h: u32
+    pub h: u32,
+    pub scale: u32,
+    pub transform: Transform
 }
 
 impl Resolution {
     pub fn new() -> Self {
         Resolution {
             w: 0,
-            h: 0
+            h: 0,
+            scale: 1,
+            transform: Transform::Normal
         }
     }
 
@@ -32,6 +68,79 @@ impl Resolution {
     pub fn size(self) -> u32 {
         self.w * self.h
     }
+
+    /// Width and height of the surface in pixels, as it is on screen.
+    fn upright(self) -> (u32, u32) {
+        if self.transform.is_sideways() {
+            (self.h, self.w)
+        } else {
+            (self.w, self.h)
+        }
+    }
+
+    /// How many pixels wide a logical unit is.
+    ///
+    /// The buffer has to be a whole number of logical units big, or setting
+    /// its scale is a protocol error, so an output whose mode doesn't divide
+    /// by its scale is drawn to at a scale of 1.
+    pub fn buffer_scale(self) -> u32 {
+        if self.w % self.scale == 0 && self.h % self.scale == 0 {
+            self.scale
+        } else {
+            1
+        }
+    }
+
+    /// Width of the surface in logical units.
+    pub fn width(self) -> u32 {
+        self.upright().0 / self.buffer_scale()
+    }
+
+    /// Height of the surface in logical units.
+    pub fn height(self) -> u32 {
+        self.upright().1 / self.buffer_scale()
+    }
+
+    /// Where the pixel at `(x, y)` on screen is in the buffer, in bytes.
+    fn offset(self, x: u32, y: u32) -> usize {
+        let (w, h) = self.upright();
+        let x = match self.transform {
+            Transform::Flipped | Transform::Flipped90 |
+            Transform::Flipped180 | Transform::Flipped270 => w - 1 - x,
+            _ => x
+        };
+        let (bx, by) = match self.transform {
+            Transform::Normal | Transform::Flipped => (x, y),
+            Transform::Rotated90 | Transform::Flipped90 => (y, w - 1 - x),
+            Transform::Rotated180 | Transform::Flipped180 => (w - 1 - x, h - 1 - y),
+            Transform::Rotated270 | Transform::Flipped270 => (h - 1 - y, x)
+        };
+        ((by * self.w + bx) * 4) as usize
+    }
+
+    /// Fills a rectangle with a color, all in logical units.
+    ///
+    /// `pixels` is the raw buffer backing a surface of this resolution, four
+    /// bytes per pixel. Anything that falls outside of it is clipped.
+    pub fn fill(self,
+                pixels: &mut [u8],
+                x: u32,
+                y: u32,
+                w: u32,
+                h: u32,
+                color: Color) {
+        let value = color.to_u32();
+        let (width, height) = self.upright();
+        let scale = self.buffer_scale();
+        let right = ::std::cmp::min((x + w) * scale, width);
+        let bottom = ::std::cmp::min((y + h) * scale, height);
+        for py in (y * scale)..bottom {
+            for px in (x * scale)..right {
+                let offset = self.offset(px, py);
+                NativeEndian::write_u32(&mut pixels[offset..offset + 4], value);
+            }
+        }
+    }
 }
 
 /// The main window struct, containing the buffer backing the wayland surface,
@@ -39,8 +148,16 @@ impl Resolution {
 pub struct Window {
     buffer: wl_buffer::WlBuffer,
     pub file: ::std::fs::File,
+    /// The size `buffer` was allocated for.
+    res: Resolution,
+    /// Used to allocate a new buffer when the output changes size.
+    shm: wl_shm::WlShm,
     pub surface: wl_surface::WlSurface,
     shell_surface: wl_shell_surface::WlShellSurface,
+    /// Lines of text drawn on top of whatever is written to the buffer.
+    overlay: Vec<String>,
+    /// Rows of buttons drawn below the text.
+    controls: Vec<Vec<Control>>
 }
 
 impl Window {
@@ -53,42 +170,29 @@ impl Window {
         let res: Resolution = *state.get_handler(resolution_id);
         assert_ne!(res.size(), 0, "Resolution was not properly initialized");
         let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
-        // Create buffer, write bytes into buffer
-        let mut file = tempfile::tempfile().ok()
-            .expect("Unable to create buffer file");
-        let mut buf = BufWriter::new(file);
-        let black: Color = 0x000000.into();
-        for _ in 0..(res.size()) {
-            buf.write_u32::<NativeEndian>(black.to_u32())
-                .expect("Could not write to temp file");
-        }
-        file = buf.into_inner()
-            .expect("Could not get buffer file");
         // Create surface
         //let surface = env.compositor.create_surface();
         let shell_surface = env.shell.get_shell_surface(&surface);
         shell_surface.set_class("Lockscreen".into());
         shell_surface.set_fullscreen(FullscreenMethod::Default, 0, Some(&output));
         shell_surface.set_maximized(Some(&output));
-        let pool = env.shm
-            .create_pool(file.as_raw_fd(), (res.w * res.h * 4) as i32);
-        let buffer = pool.create_buffer(0,
-                                        res.w as i32,
-                                        res.h as i32,
-                                        (res.w * 4) as i32,
-                                        wl_shm::Format::Argb8888)
-            .expect("Pool is already dead");
+        let (file, buffer) = allocate(&env.shm, res);
         shell_surface.set_fullscreen(FullscreenMethod::Default,
                                      0,
                                      Some(output));
+        describe_buffer(&surface, res);
         surface.attach(Some(&buffer), 0, 0);
         surface.commit();
 
         Window {
             buffer,
             file,
+            res,
+            shm: env.shm.clone().expect("Shm was not initialized"),
             surface,
             shell_surface,
+            overlay: Vec::new(),
+            controls: Vec::new()
         }
     }
 
@@ -97,44 +201,143 @@ impl Window {
             .expect("Shell surface was not initialized")
     }
 
+    /// Sets the text that is drawn on top of the buffer.
+    ///
+    /// Takes effect the next time the buffer is written to.
+    pub fn set_overlay(&mut self, overlay: Vec<String>) {
+        self.overlay = overlay;
+    }
+
+    /// Sets the rows of buttons that are drawn below the text.
+    ///
+    /// Takes effect the next time the buffer is written to.
+    pub fn set_controls(&mut self, controls: Vec<Vec<Control>>) {
+        self.controls = controls;
+    }
+
+    /// Allocates a new buffer if the output is no longer the size of the
+    /// current one, and returns whether it did.
+    ///
+    /// The new buffer is black and is shown right away, since the old one
+    /// would leave part of the output uncovered, so it needs to be redrawn.
+    pub fn resize(&mut self, res: Resolution) -> bool {
+        if res == self.res || res.size() == 0 {
+            return false
+        }
+        let (file, buffer) = allocate(&self.shm, res);
+        describe_buffer(&self.surface, res);
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface.damage(0, 0, res.width() as i32, res.height() as i32);
+        self.surface.commit();
+        self.buffer.destroy();
+        self.buffer = buffer;
+        self.file = file;
+        self.res = res;
+        true
+    }
+
     pub fn write_bytes(&mut self, res: Resolution, bytes: &[u8]) {
         assert_ne!(res.size(), 0, "Resolution was not properly initialized");
+        self.resize(res);
+        let mut pixels = bytes.to_vec();
+        // Bytes meant for the old size are cut off or padded with black,
+        // rather than writing past the end of the buffer or leaving some of
+        // it undrawn.
+        let black: Color = 0x000000.into();
+        let mut padding = [0; 4];
+        NativeEndian::write_u32(&mut padding, black.to_u32());
+        pixels.truncate((res.size() * 4) as usize);
+        while pixels.len() < (res.size() * 4) as usize {
+            pixels.extend_from_slice(&padding);
+        }
+        // The text is centered in whatever room the buttons leave.
+        let text_height = res.height().saturating_sub(controls::height(&self.controls));
+        font::draw_lines(&mut pixels, res, text_height, &self.overlay, 0xFFFFFF.into());
+        controls::draw(&mut pixels, res, &self.controls, 0xFFFFFF.into());
         self.file.seek(SeekFrom::Start(0))
             .expect("Could not seek to beginning of file");
         let file_copy = self.file.try_clone()
             .expect("Could not clone file handler");
         let mut buf = BufWriter::new(file_copy);
         // Create buffer, write bytes into buffer
-        buf.write_all(bytes)
+        buf.write_all(&pixels)
             .expect("Could not write bytes");
         self.file = buf.into_inner()
             .expect("Could not consume buffer writer");
-        self.surface.damage(0, 0, res.w as i32, res.h as i32);
+        self.surface.damage(0, 0, res.width() as i32, res.height() as i32);
         self.surface.attach(Some(&self.buffer), 0, 0);
         self.surface.commit();
     }
 
+    /// Destroys the surface and its buffer, for when the output is gone.
+    ///
+    /// The window can't be drawn to anymore afterwards.
+    pub fn destroy(&mut self) {
+        self.buffer.destroy();
+        self.surface.destroy();
+    }
+
     /// Updates the buffer to have the given color.
     pub fn update_color(&mut self,
                         color: Color,
                         res: Resolution) {
         assert_ne!(res.size(), 0, "Resolution was not properly initialized");
-        self.file.seek(SeekFrom::Start(0))
-            .expect("Could not seek to beginning of file");
-        let file_copy = self.file.try_clone()
-            .expect("Could not clone file handler");
-        let mut buf = BufWriter::new(file_copy);
-        // Create buffer, write bytes into buffer
+        let mut pixels = Vec::with_capacity((res.size() * 4) as usize);
         for _ in 0..(res.size()) {
-            buf.write_u32::<NativeEndian>(color.to_u32())
-                .expect("Could not write to temp file");
+            pixels.write_u32::<NativeEndian>(color.to_u32())
+                .expect("Could not write to pixel buffer");
         }
-        self.file = buf.into_inner()
-            .expect("Could not consume buffer writer");
-        self.surface.damage(0, 0, res.w as i32, res.h as i32);
-        self.surface.attach(Some(&self.buffer), 0, 0);
-        self.surface.commit();
+        self.write_bytes(res, &pixels);
+    }
+}
+
+/// Tells the compositor how many pixels of the buffer make up a logical
+/// unit of the surface, and how the buffer is rotated.
+fn describe_buffer(surface: &wl_surface::WlSurface, res: Resolution) {
+    // Outputs are bound so that they always report a scale of 1 if the
+    // surfaces don't know about scales.
+    if surface.version() >= 3 {
+        surface.set_buffer_scale(res.buffer_scale() as i32);
+    }
+    if surface.version() >= 2 {
+        let transform = match res.transform {
+            Transform::Normal => wl_output::Transform::Normal,
+            Transform::Rotated90 => wl_output::Transform::_90,
+            Transform::Rotated180 => wl_output::Transform::_180,
+            Transform::Rotated270 => wl_output::Transform::_270,
+            Transform::Flipped => wl_output::Transform::Flipped,
+            Transform::Flipped90 => wl_output::Transform::Flipped90,
+            Transform::Flipped180 => wl_output::Transform::Flipped180,
+            Transform::Flipped270 => wl_output::Transform::Flipped270
+        };
+        surface.set_buffer_transform(transform);
+    }
+}
+
+/// Creates a buffer of the given size, backed by a new file that is filled
+/// with black, so nothing shows through before it is drawn to.
+fn allocate(shm: &wl_shm::WlShm,
+            res: Resolution) -> (::std::fs::File, wl_buffer::WlBuffer) {
+    let file = tempfile::tempfile().ok()
+        .expect("Unable to create buffer file");
+    let mut buf = BufWriter::new(file);
+    let black: Color = 0x000000.into();
+    for _ in 0..(res.size()) {
+        buf.write_u32::<NativeEndian>(black.to_u32())
+            .expect("Could not write to temp file");
     }
+    let file = buf.into_inner()
+        .expect("Could not get buffer file");
+    let pool = shm.create_pool(file.as_raw_fd(), (res.w * res.h * 4) as i32);
+    let buffer = pool.create_buffer(0,
+                                    res.w as i32,
+                                    res.h as i32,
+                                    (res.w * 4) as i32,
+                                    wl_shm::Format::Argb8888)
+        .expect("Pool is already dead");
+    // The buffer keeps the memory mapped on its own.
+    pool.destroy();
+    (file, buffer)
 }
 
 impl wl_shell_surface::Handler for Window {
@@ -152,6 +355,29 @@ declare_handler!(Window,
                  wl_shell_surface::WlShellSurface);
 
 impl wl_output::Handler for Resolution {
+    fn geometry(&mut self,
+                _evqh: &mut EventQueueHandle,
+                _proxy: &wl_output::WlOutput,
+                _x: i32,
+                _y: i32,
+                _physical_width: i32,
+                _physical_height: i32,
+                _subpixel: wl_output::Subpixel,
+                _make: String,
+                _model: String,
+                transform: wl_output::Transform) {
+        self.transform = match transform {
+            wl_output::Transform::Normal => Transform::Normal,
+            wl_output::Transform::_90 => Transform::Rotated90,
+            wl_output::Transform::_180 => Transform::Rotated180,
+            wl_output::Transform::_270 => Transform::Rotated270,
+            wl_output::Transform::Flipped => Transform::Flipped,
+            wl_output::Transform::Flipped90 => Transform::Flipped90,
+            wl_output::Transform::Flipped180 => Transform::Flipped180,
+            wl_output::Transform::Flipped270 => Transform::Flipped270
+        };
+    }
+
     fn mode(&mut self,
             _evqh: &mut EventQueueHandle,
             _proxy: &wl_output::WlOutput,
@@ -164,6 +390,76 @@ impl wl_output::Handler for Resolution {
             self.h = height as u32;
         }
     }
+
+    fn scale(&mut self,
+             _evqh: &mut EventQueueHandle,
+             _proxy: &wl_output::WlOutput,
+             factor: i32) {
+        if factor > 0 {
+            self.scale = factor as u32;
+        }
+    }
 }
 
 declare_handler!(Resolution, wl_output::Handler, wl_output::WlOutput);
+
+#[cfg(test)]
+mod tests {
+    use super::{Resolution, Transform};
+
+    /// Which pixels of the buffer are drawn to.
+    fn drawn(res: Resolution, pixels: &[u8]) -> Vec<Vec<bool>> {
+        pixels.chunks((res.w * 4) as usize)
+            .map(|row| row.chunks(4).map(|pixel| pixel != [0; 4]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sideways_outputs_swap_width_and_height() {
+        let res = Resolution { w: 1920, h: 1080, scale: 1, transform: Transform::Rotated90 };
+        assert_eq!((res.width(), res.height()), (1080, 1920));
+        let res = Resolution { w: 3840, h: 2160, scale: 2, transform: Transform::Flipped180 };
+        assert_eq!((res.width(), res.height()), (1920, 1080));
+    }
+
+    #[test]
+    fn modes_that_do_not_divide_by_the_scale_are_not_scaled() {
+        let res = Resolution { w: 1366, h: 768, scale: 2, transform: Transform::Normal };
+        assert_eq!(res.buffer_scale(), 2);
+        let res = Resolution { w: 1366, h: 768, scale: 3, transform: Transform::Normal };
+        assert_eq!(res.buffer_scale(), 1);
+        assert_eq!((res.width(), res.height()), (1366, 768));
+    }
+
+    #[test]
+    fn drawing_follows_the_transform() {
+        let draw = |transform| {
+            let res = Resolution { w: 3, h: 2, scale: 1, transform };
+            let mut pixels = vec![0; (res.size() * 4) as usize];
+            // The top left corner, as it is on screen.
+            res.fill(&mut pixels, 0, 0, 1, 1, 0xFFFFFF.into());
+            drawn(res, &pixels)
+        };
+        assert_eq!(draw(Transform::Normal), vec![vec![true, false, false],
+                                                 vec![false, false, false]]);
+        assert_eq!(draw(Transform::Rotated90), vec![vec![false, false, false],
+                                                    vec![true, false, false]]);
+        assert_eq!(draw(Transform::Rotated180), vec![vec![false, false, false],
+                                                     vec![false, false, true]]);
+        assert_eq!(draw(Transform::Rotated270), vec![vec![false, false, true],
+                                                     vec![false, false, false]]);
+        assert_eq!(draw(Transform::Flipped), vec![vec![false, false, true],
+                                                  vec![false, false, false]]);
+    }
+
+    #[test]
+    fn drawing_fills_whole_logical_units() {
+        let res = Resolution { w: 4, h: 4, scale: 2, transform: Transform::Normal };
+        let mut pixels = vec![0; (res.size() * 4) as usize];
+        res.fill(&mut pixels, 1, 0, 1, 1, 0xFFFFFF.into());
+        assert_eq!(drawn(res, &pixels), vec![vec![false, false, true, true],
+                                             vec![false, false, true, true],
+                                             vec![false, false, false, false],
+                                             vec![false, false, false, false]]);
+    }
+}
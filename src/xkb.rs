@@ -0,0 +1,74 @@
+//! Raw bindings to libxkbcommon, as declared in `xkbcommon/xkbcommon.h` and
+//! `xkbcommon/xkbcommon-compose.h`.
+//!
+//! `wayland_kbd` loads the library for its own use, but doesn't expose it.
+//! Only what `wc-lock` actually uses is declared here.
+
+#![allow(non_camel_case_types, non_upper_case_globals, dead_code)]
+
+use libc::{c_char, c_int, size_t};
+
+pub enum xkb_context {}
+pub enum xkb_keymap {}
+pub enum xkb_compose_table {}
+pub enum xkb_compose_state {}
+
+pub const XKB_CONTEXT_NO_FLAGS: c_int = 0;
+pub const XKB_KEYMAP_FORMAT_TEXT_V1: c_int = 1;
+pub const XKB_KEYMAP_COMPILE_NO_FLAGS: c_int = 0;
+pub const XKB_KEYSYM_CASE_INSENSITIVE: c_int = 1;
+pub const XKB_KEY_NoSymbol: u32 = 0;
+pub const XKB_MOD_INVALID: u32 = 0xffff_ffff;
+
+pub const XKB_COMPOSE_COMPILE_NO_FLAGS: c_int = 0;
+pub const XKB_COMPOSE_STATE_NO_FLAGS: c_int = 0;
+pub const XKB_COMPOSE_FORMAT_TEXT_V1: c_int = 1;
+
+// xkb_compose_feed_result
+pub const XKB_COMPOSE_FEED_IGNORED: c_int = 0;
+pub const XKB_COMPOSE_FEED_ACCEPTED: c_int = 1;
+
+// xkb_compose_status
+pub const XKB_COMPOSE_NOTHING: c_int = 0;
+pub const XKB_COMPOSE_COMPOSING: c_int = 1;
+pub const XKB_COMPOSE_COMPOSED: c_int = 2;
+pub const XKB_COMPOSE_CANCELLED: c_int = 3;
+
+#[link(name = "xkbcommon")]
+extern "C" {
+    pub fn xkb_context_new(flags: c_int) -> *mut xkb_context;
+    pub fn xkb_context_unref(context: *mut xkb_context);
+
+    pub fn xkb_keymap_new_from_string(context: *mut xkb_context,
+                                      string: *const c_char,
+                                      format: c_int,
+                                      flags: c_int) -> *mut xkb_keymap;
+    pub fn xkb_keymap_unref(keymap: *mut xkb_keymap);
+    pub fn xkb_keymap_num_layouts(keymap: *mut xkb_keymap) -> u32;
+    pub fn xkb_keymap_layout_get_name(keymap: *mut xkb_keymap,
+                                      index: u32) -> *const c_char;
+    pub fn xkb_keymap_mod_get_index(keymap: *mut xkb_keymap,
+                                    name: *const c_char) -> u32;
+
+    pub fn xkb_keysym_from_name(name: *const c_char, flags: c_int) -> u32;
+
+    pub fn xkb_compose_table_new_from_locale(context: *mut xkb_context,
+                                             locale: *const c_char,
+                                             flags: c_int) -> *mut xkb_compose_table;
+    pub fn xkb_compose_table_new_from_buffer(context: *mut xkb_context,
+                                             buffer: *const c_char,
+                                             length: size_t,
+                                             locale: *const c_char,
+                                             format: c_int,
+                                             flags: c_int) -> *mut xkb_compose_table;
+    pub fn xkb_compose_table_unref(table: *mut xkb_compose_table);
+    pub fn xkb_compose_state_new(table: *mut xkb_compose_table,
+                                 flags: c_int) -> *mut xkb_compose_state;
+    pub fn xkb_compose_state_unref(state: *mut xkb_compose_state);
+    pub fn xkb_compose_state_feed(state: *mut xkb_compose_state, keysym: u32) -> c_int;
+    pub fn xkb_compose_state_reset(state: *mut xkb_compose_state);
+    pub fn xkb_compose_state_get_status(state: *mut xkb_compose_state) -> c_int;
+    pub fn xkb_compose_state_get_utf8(state: *mut xkb_compose_state,
+                                      buffer: *mut c_char,
+                                      size: size_t) -> c_int;
+}